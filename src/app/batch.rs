@@ -0,0 +1,459 @@
+//! The `batch` command, which runs the text completions of many rows from a jsonl or csv file.
+
+use super::text_completion::{self, Parameters};
+use crate::config::Defaults;
+use crate::verbosity::{info, tip, verbose};
+use crate::{SynthTextBatch, TopKFromStrAdapter, TopPFromStrAdapter};
+use anyhow::Context;
+use futures::StreamExt;
+use owo_colors::OwoColorize;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    /// One json object per line.
+    Jsonl,
+
+    /// Comma separated values, with a header.
+    Csv,
+}
+
+impl InputFormat {
+    /// Pick the format from the extension of the path, falling back to jsonl.
+    fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("csv") => Self::Csv,
+            _ => Self::Jsonl,
+        }
+    }
+}
+
+impl fmt::Display for InputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Jsonl => "jsonl",
+            Self::Csv => "csv",
+        })
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "jsonl" | "ndjson" => Ok(Self::Jsonl),
+            "csv" => Ok(Self::Csv),
+            _ => anyhow::bail!("unknown format {}; expected one of jsonl or csv", s.bold()),
+        }
+    }
+}
+
+/// Stop strings, which are a single string in csv files.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Until {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Deserialize)]
+struct Row {
+    #[serde(default)]
+    id: Option<serde_json::Value>,
+
+    prompt: String,
+
+    #[serde(default)]
+    max_tokens: Option<usize>,
+
+    #[serde(default)]
+    temperature: Option<f64>,
+
+    #[serde(default)]
+    top_k: Option<u16>,
+
+    #[serde(default)]
+    top_p: Option<f64>,
+
+    #[serde(default)]
+    until: Option<Until>,
+}
+
+/// Read every row of the input. A row which can't be parsed doesn't stop the batch; its error
+/// becomes its result instead.
+fn read_rows(input: &Path, format: InputFormat) -> anyhow::Result<Vec<anyhow::Result<Row>>> {
+    let mut source = String::new();
+
+    if input == Path::new("-") {
+        io::stdin()
+            .read_to_string(&mut source)
+            .context("failed to read standard input")?;
+    } else {
+        source = fs::read_to_string(input)
+            .with_context(|| format!("failed to read the rows from {}", input.display().bold()))?;
+    }
+
+    let rows = match format {
+        InputFormat::Jsonl => source
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str::<Row>(line).context("invalid row"))
+            .collect(),
+        InputFormat::Csv => csv::Reader::from_reader(source.as_bytes())
+            .into_deserialize::<Row>()
+            .map(|row| row.context("invalid row"))
+            .collect(),
+    };
+
+    Ok(rows)
+}
+
+/// What the result of a row is matched by when resuming: its id when it has one, and its position
+/// otherwise.
+#[derive(PartialEq, Eq, Hash)]
+enum Key {
+    Id(String),
+    Index(usize),
+}
+
+impl Key {
+    fn new(index: usize, id: Option<&serde_json::Value>) -> Self {
+        match id {
+            Some(id) => Self::Id(id.to_string()),
+            None => Self::Index(index),
+        }
+    }
+}
+
+/// The successful results in the output file of a previous run, by the row they belong to.
+fn completed_rows(path: &Path) -> anyhow::Result<HashMap<Key, serde_json::Value>> {
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+
+    let results = fs::read_to_string(path)
+        .with_context(|| format!("failed to read the results at {}", path.display().bold()))?;
+    let mut completed = HashMap::new();
+
+    for line in results.lines() {
+        // a line which was cut off when the previous run was interrupted is run again
+        let result = match serde_json::from_str::<serde_json::Value>(line) {
+            Ok(result) => result,
+            Err(_) => continue,
+        };
+
+        if result.get("error").is_none() {
+            if let Some(index) = result.get("index").and_then(|index| index.as_u64()) {
+                completed.insert(Key::new(index as usize, result.get("id")), result);
+            }
+        }
+    }
+
+    Ok(completed)
+}
+
+/// Run the text completion of a single row, falling back to the flags for the parameters the row
+/// doesn't set.
+async fn complete(row: Row, batch: &SynthTextBatch) -> anyhow::Result<serde_json::Value> {
+    let top_k = match row.top_k {
+        Some(top_k) => Some(TopKFromStrAdapter::try_from(top_k)?),
+        None => batch.top_k.clone(),
+    };
+    let top_p = match row.top_p {
+        Some(top_p) => Some(TopPFromStrAdapter::try_from(top_p)?),
+        None => batch.top_p.clone(),
+    };
+    let until = match row.until {
+        Some(Until::One(until)) if until.is_empty() => Vec::new(),
+        Some(Until::One(until)) => vec![until],
+        Some(Until::Many(until)) => until,
+        None => batch.until.clone(),
+    };
+    let parameters = Parameters::new(
+        row.max_tokens.or(batch.max_tokens),
+        row.temperature.or(batch.temperature),
+        top_k,
+        top_p,
+        until,
+        Defaults::default(),
+    )?;
+    let text_completion = text_completion::complete(&row.prompt, &parameters).await?;
+
+    Ok(serde_json::json!({
+        "text": text_completion.text(),
+        "truncated_prompt": text_completion.truncated_prompt(),
+        "total_tokens": text_completion.total_tokens(),
+        "parameters": parameters,
+    }))
+}
+
+pub async fn run(batch: SynthTextBatch) -> anyhow::Result<()> {
+    if batch.concurrency == 0 {
+        anyhow::bail!("the concurrency must be at least 1")
+    }
+
+    let format = batch
+        .format
+        .unwrap_or_else(|| InputFormat::from_path(&batch.input));
+    let rows = read_rows(&batch.input, format)?;
+    let total = rows.len();
+
+    let mut completed = match (&batch.output_file, batch.resume) {
+        (Some(output_file), true) => completed_rows(output_file)?,
+        (None, true) => anyhow::bail!("{} needs {}", "--resume".bold(), "--output-file".bold()),
+        (Some(output_file), false) if output_file.exists() => {
+            tip!(
+                "pass {} to continue it, or remove it first",
+                "--resume".italic()
+            );
+            anyhow::bail!(
+                "the output file {} already exists",
+                output_file.display().bold()
+            )
+        }
+        (_, false) => HashMap::new(),
+    };
+
+    // a resumed output file is written again next to it in the order of the rows, and only
+    // replaced once every row ran, so that the previous results aren't lost if it's interrupted
+    let temporary = match batch.output_file.as_ref().filter(|_| batch.resume) {
+        Some(output_file) => {
+            let file_name = output_file.file_name().with_context(|| {
+                format!("the path {} has no file name", output_file.display().bold())
+            })?;
+
+            Some(output_file.with_file_name(format!(".{}.tmp", file_name.to_string_lossy())))
+        }
+        None => None,
+    };
+
+    let mut output: Box<dyn Write> = match temporary.as_ref().or(batch.output_file.as_ref()) {
+        Some(path) => File::create(path)
+            .with_context(|| format!("failed to open the output file {}", path.display().bold()))
+            .map(|file| Box::new(file) as Box<dyn Write>)?,
+        None => Box::new(io::stdout()),
+    };
+
+    let started = Instant::now();
+    let rows = rows
+        .into_iter()
+        .enumerate()
+        .map(|(index, row)| {
+            let id = row.as_ref().ok().and_then(|row| row.id.clone());
+            let previous = completed.remove(&Key::new(index, id.as_ref()));
+
+            (index, id, row, previous)
+        })
+        .collect::<Vec<_>>();
+    let skipped = rows
+        .iter()
+        .filter(|(_, _, _, previous)| previous.is_some())
+        .count();
+    let mut failed = 0;
+
+    if skipped > 0 {
+        info!("skipping {} row(s) which already have a result", skipped);
+    }
+
+    // `buffered` runs up to `concurrency` rows at the same time, but yields their results in the
+    // order of the rows
+    let mut results = futures::stream::iter(rows)
+        .map(|(index, id, row, previous)| {
+            let batch = &batch;
+
+            async move {
+                let result = match (previous, row) {
+                    (Some(previous), _) => return (index, id, Ok(previous), true),
+                    (None, Ok(row)) => complete(row, batch).await,
+                    (None, Err(error)) => Err(error),
+                };
+
+                (index, id, result, false)
+            }
+        })
+        .buffered(batch.concurrency);
+
+    while let Some((index, id, result, kept)) = results.next().await {
+        let mut line = serde_json::json!({ "index": index });
+
+        if let Some(id) = id {
+            line["id"] = id;
+        }
+
+        match result {
+            Ok(result) => {
+                if !kept {
+                    verbose!("row {} completed", index);
+                }
+
+                // a previous result matched by id may have been at another position, so its index
+                // is the one set above
+                if let serde_json::Value::Object(result) = result {
+                    for (key, value) in result {
+                        if !(kept && key == "index") {
+                            line[key] = value;
+                        }
+                    }
+                }
+            }
+            Err(error) => {
+                failed += 1;
+                verbose!("row {} failed: {:#}", index, error);
+                line["error"] = format!("{:#}", error).into();
+            }
+        }
+
+        writeln!(output, "{}", line).context("failed to write a result")?;
+        output.flush().context("failed to flush the results")?;
+    }
+
+    if let (Some(temporary), Some(output_file)) = (&temporary, &batch.output_file) {
+        drop(output);
+        fs::rename(temporary, output_file).with_context(|| {
+            format!(
+                "failed to move path {} to {}",
+                temporary.display().bold(),
+                output_file.display().bold()
+            )
+        })?;
+    }
+
+    info!(
+        "ran {} row(s) in {:.1} s; {} failed",
+        total - skipped,
+        started.elapsed().as_secs_f64(),
+        failed
+    );
+
+    if failed > 0 {
+        if batch.output_file.is_some() {
+            tip!("pass {} to run the failed rows again", "--resume".italic());
+        }
+
+        anyhow::bail!(
+            "{} of {} row(s) failed; their results have an {} field",
+            failed,
+            total,
+            "error".bold()
+        )
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run;
+    use crate::SynthTextBatch;
+    use clap::Parser;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    /// Resume the batch of the rows, with the results of a previous run in the output file.
+    async fn resume(name: &str, rows: &str, results: &str) -> (anyhow::Result<()>, PathBuf) {
+        let directory =
+            std::env::temp_dir().join(format!("synthtext-batch-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let input = directory.join("rows.jsonl");
+        let output = directory.join("results.jsonl");
+        fs::write(&input, rows).unwrap();
+        fs::write(&output, results).unwrap();
+
+        let batch = SynthTextBatch::parse_from([
+            "batch".as_ref(),
+            input.as_os_str(),
+            "--output-file".as_ref(),
+            output.as_os_str(),
+            "--resume".as_ref(),
+        ]);
+
+        (run(batch).await, directory)
+    }
+
+    fn results(directory: &Path) -> Vec<serde_json::Value> {
+        fs::read_to_string(directory.join("results.jsonl"))
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn failed_middle_row_is_replaced_in_place() {
+        // the middle row has no prompt, so it fails again without making a request
+        let (result, directory) = resume(
+            "failed-middle-row",
+            concat!(
+                r#"{"id": "a", "prompt": "first"}"#,
+                "\n",
+                r#"{"id": "b"}"#,
+                "\n",
+                r#"{"id": "c", "prompt": "third"}"#,
+                "\n",
+            ),
+            concat!(
+                r#"{"index": 0, "id": "a", "text": "one"}"#,
+                "\n",
+                r#"{"index": 1, "id": "b", "error": "failed"}"#,
+                "\n",
+                r#"{"index": 2, "id": "c", "text": "three"}"#,
+                "\n",
+            ),
+        )
+        .await;
+        let results = results(&directory);
+
+        assert!(result.is_err());
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0]["text"], "one");
+        assert_eq!(results[1]["index"], 1);
+        assert!(results[1]["error"]
+            .as_str()
+            .unwrap()
+            .contains("invalid row"));
+        assert_eq!(results[2]["text"], "three");
+        assert!(!directory.join(".results.jsonl.tmp").exists());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn results_are_matched_by_id() {
+        let (result, directory) = resume(
+            "matched-by-id",
+            concat!(
+                r#"{"id": "c", "prompt": "third"}"#,
+                "\n",
+                r#"{"id": "a", "prompt": "first"}"#,
+                "\n",
+            ),
+            concat!(
+                r#"{"index": 0, "id": "a", "text": "one"}"#,
+                "\n",
+                r#"{"index": 1, "id": "c", "text": "three"}"#,
+                "\n",
+            ),
+        )
+        .await;
+        let results = results(&directory);
+
+        result.unwrap();
+        assert_eq!(
+            results,
+            [
+                serde_json::json!({ "index": 0, "id": "c", "text": "three" }),
+                serde_json::json!({ "index": 1, "id": "a", "text": "one" }),
+            ]
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,335 @@
+//! The `config validate` and `doctor` commands.
+
+use crate::config::{permissions, Connection};
+use crate::config::resolve::{engine_definition_to_string, Overrides, Resolved};
+use crate::output::{self, Output};
+use crate::verbosity::info;
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tap::Pipe;
+use textsynth::prelude::MaxTokens;
+
+/// The timeout of the checks which make requests in seconds, unless another one was configured.
+const TIMEOUT: f64 = 10.0;
+
+/// The outcomes of the checks, which are printed as they're made, or written as a single json
+/// document at the end for the other outputs.
+struct Report {
+    output: Output,
+    checks: Vec<serde_json::Value>,
+}
+
+impl Report {
+    fn new() -> Self {
+        Self {
+            output: output::get(),
+            checks: Vec::new(),
+        }
+    }
+
+    /// Record the outcome of a check, returning whether it passed.
+    fn check(&mut self, name: &str, result: anyhow::Result<String>) -> bool {
+        let passed = result.is_ok();
+
+        // the text report goes to stdout as a whole, like the paths and settings it's printed with
+        match (self.output, result) {
+            (Output::Text, Ok(detail)) => println!("{} {}: {}", "ok".green(), name.bold(), detail),
+            (Output::Text, Err(error)) => {
+                println!("{} {}: {:#}", "failed".red(), name.bold(), error)
+            }
+            (_, Ok(detail)) => self.checks.push(serde_json::json!({
+                "name": name,
+                "passed": true,
+                "detail": detail,
+            })),
+            (_, Err(error)) => self.checks.push(serde_json::json!({
+                "name": name,
+                "passed": false,
+                "error": format!("{:#}", error),
+            })),
+        }
+
+        passed
+    }
+
+    /// Write the document with the checks added to it, unless the output is text, then fail with
+    /// the message if any check failed.
+    fn finish(
+        self,
+        mut document: serde_json::Value,
+        passed: bool,
+        failure: &str,
+    ) -> anyhow::Result<()> {
+        if !self.output.is_text() {
+            document["passed"] = passed.into();
+            document["checks"] = self.checks.into();
+            self.output.emit(&document)?;
+        }
+
+        if !passed {
+            anyhow::bail!("{}", failure)
+        }
+
+        Ok(())
+    }
+}
+
+/// Why `config validate` fails.
+const INVALID: &str = "the configuration is not valid";
+
+fn check_engine_definition(resolved: &Resolved) -> anyhow::Result<String> {
+    let engine_definition = &resolved.engine_definition.value;
+
+    if engine_definition.max_tokens() == 0 {
+        anyhow::bail!("the maximum number of tokens of the engine definition must not be 0")
+    }
+
+    Ok(format!(
+        "{} with at most {} tokens (from {})",
+        engine_definition_to_string(engine_definition),
+        engine_definition.max_tokens(),
+        resolved.engine_definition.source
+    ))
+}
+
+fn check_max_tokens(resolved: &Resolved) -> anyhow::Result<String> {
+    let max_tokens = match &resolved.max_tokens {
+        Some(max_tokens) => max_tokens,
+        None => return Ok("not set".to_string()),
+    };
+    let engine_definition = &resolved.engine_definition.value;
+
+    if MaxTokens::new(max_tokens.value, engine_definition).is_none() {
+        anyhow::bail!(
+            "{} (from {}) doesn't fit in the engine definition, which supports at most {}",
+            max_tokens.value,
+            max_tokens.source,
+            engine_definition.max_tokens()
+        )
+    }
+
+    Ok(format!("{} (from {})", max_tokens.value, max_tokens.source))
+}
+
+/// Make the cheapest possible request, the log probability of a single character after the
+/// End-Of-Text token, with the same client as the other commands.
+async fn check_log_probabilities(
+    resolved: &Resolved,
+    connection: &Connection,
+) -> anyhow::Result<String> {
+    let api_key = resolved.required_api_key()?;
+    let client = crate::textsynth::Client::new(api_key, connection)?;
+    let start = Instant::now();
+
+    client
+        .engine(resolved.engine_definition.value.clone())
+        .log_probabilities("", " ")
+        .await
+        .context("failed to get log probabilities")?;
+
+    Ok(format!("answered in {} ms", start.elapsed().as_millis()))
+}
+
+fn check_connection(resolved: &Resolved) -> anyhow::Result<String> {
+    let connection = resolved.connection();
+
+    if let Some(ca_bundle) = &connection.ca_bundle {
+        fs::read(ca_bundle)
+            .with_context(|| format!("failed to read path {}", ca_bundle.display().bold()))
+            .and_then(|contents| {
+                reqwest::Certificate::from_pem(&contents).with_context(|| {
+                    format!("{} is not a pem file", ca_bundle.display().bold())
+                })
+            })?;
+    }
+
+    match &connection.proxy {
+        Some(proxy) => Ok(format!("through proxy {}", crate::config::redact_url(proxy))),
+        None => Ok("direct".to_string()),
+    }
+}
+
+/// Check the configuration, without making any requests unless `online` is true.
+pub async fn validate(
+    config_path_override: Option<PathBuf>,
+    overrides: Overrides,
+    online: bool,
+) -> anyhow::Result<()> {
+    let mut report = Report::new();
+    let layers = crate::config::load_layers(config_path_override.as_deref());
+    let layers = match layers {
+        Ok(layers) => {
+            let locations = layers
+                .iter()
+                .map(|layer| layer.location.display().to_string())
+                .collect::<Vec<_>>();
+            let detail = if locations.is_empty() {
+                "no config files; using the built-in defaults and the environment".to_string()
+            } else {
+                locations.join(", ")
+            };
+            report.check("config files", Ok(detail));
+            layers
+        }
+        Err(error) => {
+            report.check("config files", Err(error));
+            return report.finish(serde_json::json!({}), false, INVALID);
+        }
+    };
+    let mut passed = true;
+
+    for layer in layers.iter().filter(|layer| !layer.project) {
+        let result = permissions::accessible_by_others(&layer.location).and_then(|mode| match mode {
+            Some(mode) => anyhow::bail!(
+                "{} can be accessed by other users (permissions {:o}); run {}",
+                layer.location.display(),
+                mode,
+                permissions::fix_command(&layer.location).italic()
+            ),
+            None => Ok(format!("{} is private", layer.location.display())),
+        });
+        passed &= report.check("permissions", result);
+    }
+
+    let resolved = match crate::config::resolve::resolve(&layers, overrides) {
+        Ok(resolved) => {
+            let profile = match &resolved.profile {
+                Some(profile) => format!("profile {} (from {})", profile.value, profile.source),
+                None => "no profile".to_string(),
+            };
+            report.check("resolution", Ok(profile));
+            resolved
+        }
+        Err(error) => {
+            report.check("resolution", Err(error));
+            return report.finish(serde_json::json!({}), false, INVALID);
+        }
+    };
+    let api_key = match &resolved.api_key {
+        Some(api_key) => Ok(format!("{} (from {})", api_key.value, api_key.source)),
+        None => Err(anyhow::anyhow!("no api key was specified")),
+    };
+    passed &= report.check("api key", api_key);
+    passed &= report.check("engine definition", check_engine_definition(&resolved));
+    passed &= report.check("default max_tokens", check_max_tokens(&resolved));
+    passed &= report.check("connection", check_connection(&resolved));
+
+    if online {
+        passed &= report.check(
+            "log probabilities request",
+            check_log_probabilities(&resolved, &resolved.connection()).await,
+        );
+    }
+
+    report.finish(serde_json::json!({}), passed, INVALID)?;
+    info!("the configuration is valid");
+
+    Ok(())
+}
+
+async fn check_reachability(
+    client: &reqwest::Client,
+    base_url: &str,
+    timeout: Option<Duration>,
+) -> anyhow::Result<String> {
+    let start = Instant::now();
+    let response = client
+        .get(base_url)
+        .pipe(|request| match timeout {
+            Some(timeout) => request.timeout(timeout),
+            None => request,
+        })
+        .send()
+        .await
+        .with_context(|| format!("failed to connect to {}", base_url.bold()))?;
+
+    Ok(format!(
+        "answered with {} in {} ms",
+        response.status(),
+        start.elapsed().as_millis()
+    ))
+}
+
+/// Fill in the timeouts of the checks, unless others were configured.
+fn with_default_timeouts(mut connection: Connection) -> Connection {
+    connection.timeout.get_or_insert(TIMEOUT);
+    connection.connect_timeout.get_or_insert(TIMEOUT);
+    connection
+}
+
+/// Report the config paths, the effective settings and whether the api can be reached.
+pub async fn doctor(config_path_override: Option<PathBuf>, overrides: Overrides) -> anyhow::Result<()> {
+    let mut report = Report::new();
+    let text = report.output.is_text();
+
+    if text {
+        println!("{}", "config paths".bold().underline());
+        super::config::find_path(config_path_override.clone())?;
+
+        println!("{}", "effective settings".bold().underline());
+    }
+
+    let cli_connection = overrides.connection.clone();
+    let resolved = crate::config::load_layers(config_path_override.as_deref())
+        .and_then(|layers| crate::config::resolve::resolve(&layers, overrides));
+    let resolved = match resolved {
+        Ok(resolved) => {
+            if text {
+                super::config::print_resolved(&resolved);
+            }
+
+            Some(resolved)
+        }
+        Err(error) => {
+            report.check("configuration", Err(error));
+            None
+        }
+    };
+
+    // fall back to the command line flags if the configuration couldn't be resolved
+    let connection = resolved
+        .as_ref()
+        .map(Resolved::connection)
+        .unwrap_or(cli_connection)
+        .pipe(with_default_timeouts);
+    let base_url = connection
+        .base_url
+        .clone()
+        .unwrap_or_else(|| crate::textsynth::DEFAULT_BASE_URL.to_string());
+
+    if text {
+        println!("{} {}", "api".bold().underline(), base_url.italic());
+    }
+
+    let client = crate::textsynth::http_client(&connection)?;
+    let timeout = connection.timeout()?;
+    let mut passed = report.check(
+        "reachability",
+        check_reachability(&client, &base_url, timeout).await,
+    );
+
+    match &resolved {
+        Some(resolved) => {
+            passed &= report.check(
+                "log probabilities request",
+                check_log_probabilities(resolved, &connection).await,
+            );
+        }
+        None => passed = false,
+    }
+
+    let document = if text {
+        serde_json::Value::Null
+    } else {
+        serde_json::json!({
+            "config_paths": super::config::paths_json(config_path_override.as_deref())?,
+            "settings": resolved.as_ref().map(super::config::resolved_json),
+            "api": base_url,
+        })
+    };
+
+    report.finish(document, passed, "some checks failed")
+}
@@ -1,14 +1,24 @@
+pub mod batch;
+mod diagnostics;
+pub mod repl;
+pub mod template;
 mod text_completion;
+mod wizard;
 pub mod config {
-    use crate::config::Config;
+    use crate::config::resolve::{engine_definition_to_string, Overrides, Resolved, Sourced};
+    use crate::config::format::Format;
+    use crate::config::{crypto, edit, migrate, Config, Profile};
+    use crate::verbosity::{info, tip, warn};
     use crate::EngineDefinitionFromStrAdapter;
+    pub use super::diagnostics::validate;
+    pub use super::wizard::init;
     use anyhow::Context;
     use owo_colors::OwoColorize;
+    use serde_json::json;
 
     use std::io::Write;
     use std::path::{Path, PathBuf};
     use std::{env, fs, io};
-    use tap::Tap;
 
     fn existing(path: &Path) -> String {
         if path.exists() {
@@ -18,193 +28,731 @@ pub mod config {
         }
     }
 
-    pub fn find_path(config_path_override: Option<PathBuf>) {
+    fn path_json(path: &Path) -> serde_json::Value {
+        json!({ "path": path, "exists": path.exists() })
+    }
+
+    /// The config paths and whether they exist, as a json object.
+    pub fn paths_json(config_path_override: Option<&Path>) -> anyhow::Result<serde_json::Value> {
+        let candidates = crate::config::paths::candidates();
+        let project_locations = crate::config::paths::project_locations()
+            .context("failed to find project-local config files")?;
+
+        Ok(json!({
+            "location": path_json(crate::config::paths::location()),
+            "override": config_path_override.map(path_json),
+            "candidates": candidates.iter().map(|candidate| path_json(candidate)).collect::<Vec<_>>(),
+            "project": project_locations,
+        }))
+    }
+
+    pub fn find_path(config_path_override: Option<PathBuf>) -> anyhow::Result<()> {
+        let output = crate::output::get();
+
+        if !output.is_text() {
+            return output.emit(&paths_json(config_path_override.as_deref())?);
+        }
+
         let default_config_path = crate::config::paths::location();
 
         match config_path_override {
             Some(config_path_override) => {
-                alp::info!(
+                println!(
                     "the config path {} be located at {} {}",
                     "would".italic(),
                     default_config_path.display().bold(),
                     existing(default_config_path)
                 );
-                alp::info!(
+                println!(
                     "...but it was overridden to {} {}",
                     config_path_override.display().bold(),
                     existing(&config_path_override)
                 )
             }
             None => {
-                alp::info!(
+                println!(
                     "the config path is located at {} {}",
                     default_config_path.display().bold(),
                     existing(default_config_path)
-                )
+                );
+                println!("the config is looked for at these paths, in order:");
+
+                for candidate in crate::config::paths::candidates() {
+                    println!("  {} {}", candidate.display().bold(), existing(&candidate));
+                }
+            }
+        }
+
+        match crate::config::paths::project_locations() {
+            Ok(project_locations) if project_locations.is_empty() => {
+                println!("no project-local config files were found")
+            }
+            Ok(project_locations) => {
+                println!("project-local config files, layered over the config above from the lowest to the highest precedence:");
+
+                for (index, location) in project_locations.iter().enumerate() {
+                    println!("  {}. {}", (index + 1).bold(), location.display().bold());
+                }
             }
+            Err(error) => warn!("failed to find project-local config files: {:#}", error),
         }
+
+        Ok(())
     }
 
-    enum FileOrStdout {
-        File { handle: fs::File, path: PathBuf },
-        Stdout(io::Stdout),
+    /// A resolved value, as json, and where it came from.
+    type Entry = Option<(serde_json::Value, String)>;
+
+    fn resolved_entry<T>(
+        sourced: Option<&Sourced<T>>,
+        value: impl FnOnce(&T) -> serde_json::Value,
+    ) -> Entry {
+        sourced.map(|sourced| (value(&sourced.value), sourced.source.to_string()))
     }
 
-    impl io::Write for FileOrStdout {
-        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-            match self {
-                FileOrStdout::File { handle, .. } => handle.write(buf),
-                FileOrStdout::Stdout(stdout) => stdout.write(buf),
+    /// Each effective value and where it came from, with secrets redacted.
+    fn resolved_entries(resolved: &Resolved) -> Vec<(&'static str, Entry)> {
+        vec![
+            (
+                "profile",
+                resolved_entry(resolved.profile.as_ref(), |profile| json!(profile)),
+            ),
+            (
+                "api_key",
+                resolved_entry(resolved.api_key.as_ref(), |api_key| {
+                    json!(api_key.to_string())
+                }),
+            ),
+            (
+                "engine_definition",
+                resolved_entry(Some(&resolved.engine_definition), |engine_definition| {
+                    json!(engine_definition_to_string(engine_definition))
+                }),
+            ),
+            (
+                "max_tokens",
+                resolved_entry(resolved.max_tokens.as_ref(), |value| json!(value)),
+            ),
+            (
+                "temperature",
+                resolved_entry(resolved.temperature.as_ref(), |value| json!(value)),
+            ),
+            (
+                "top_k",
+                resolved_entry(resolved.top_k.as_ref(), |value| json!(value)),
+            ),
+            (
+                "top_p",
+                resolved_entry(resolved.top_p.as_ref(), |value| json!(value)),
+            ),
+            (
+                "until",
+                resolved_entry(resolved.until.as_ref(), |value| json!(value)),
+            ),
+            (
+                "base_url",
+                resolved_entry(resolved.base_url.as_ref(), |value| json!(value)),
+            ),
+            (
+                "proxy",
+                resolved_entry(resolved.proxy.as_ref(), |proxy| {
+                    json!(crate::config::redact_url(proxy))
+                }),
+            ),
+            (
+                "timeout",
+                resolved_entry(resolved.timeout.as_ref(), |value| json!(value)),
+            ),
+            (
+                "connect_timeout",
+                resolved_entry(resolved.connect_timeout.as_ref(), |value| json!(value)),
+            ),
+            (
+                "ca_bundle",
+                resolved_entry(resolved.ca_bundle.as_ref(), |ca_bundle| {
+                    json!(ca_bundle.display().to_string())
+                }),
+            ),
+            (
+                "max_attempts",
+                resolved_entry(resolved.max_attempts.as_ref(), |value| json!(value)),
+            ),
+            (
+                "base_delay",
+                resolved_entry(resolved.base_delay.as_ref(), |value| json!(value)),
+            ),
+            (
+                "max_delay",
+                resolved_entry(resolved.max_delay.as_ref(), |value| json!(value)),
+            ),
+            (
+                "jitter",
+                resolved_entry(resolved.jitter.as_ref(), |value| json!(value)),
+            ),
+        ]
+    }
+
+    /// Print each effective value and where it came from.
+    pub fn print_resolved(resolved: &Resolved) {
+        for (name, entry) in resolved_entries(resolved) {
+            match entry {
+                Some((value, source)) => {
+                    let value = match value {
+                        serde_json::Value::String(value) => value,
+                        value => value.to_string(),
+                    };
+
+                    println!(
+                        "{}: {} {}",
+                        name.bold(),
+                        value,
+                        format_args!("(from {})", source).italic()
+                    )
+                }
+                None => println!("{}: {}", name.bold(), "(not set)".italic()),
             }
         }
+    }
+
+    /// Each effective value and where it came from, as a json object.
+    pub fn resolved_json(resolved: &Resolved) -> serde_json::Value {
+        resolved_entries(resolved)
+            .into_iter()
+            .map(|(name, entry)| {
+                let entry = match entry {
+                    Some((value, source)) => json!({ "value": value, "source": source }),
+                    None => serde_json::Value::Null,
+                };
+
+                (name.to_string(), entry)
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+
+    pub fn show(
+        config_path_override: Option<PathBuf>,
+        overrides: Overrides,
+        resolved: bool,
+    ) -> anyhow::Result<()> {
+        if resolved {
+            let layers = crate::config::load_layers(config_path_override.as_deref())?;
+            let resolved = crate::config::resolve::resolve(&layers, overrides)
+                .context("failed to resolve the configuration")?;
+            let output = crate::output::get();
 
-        fn flush(&mut self) -> io::Result<()> {
-            match self {
-                FileOrStdout::File { handle, .. } => handle.flush(),
-                FileOrStdout::Stdout(stdout) => stdout.flush(),
+            if !output.is_text() {
+                return output.emit(&resolved_json(&resolved));
             }
+
+            print_resolved(&resolved);
+
+            return Ok(());
+        }
+
+        let location = edit_location(config_path_override);
+
+        if !location.exists() {
+            info!("there is no config file at {}", location.display().bold());
+            return Ok(());
         }
+
+        let mut config = crate::config::load_with_location(&location).with_context(|| {
+            format!("failed to load the config at {}", location.display().bold())
+        })?;
+        let profiles = std::iter::once(&mut config.base).chain(config.profiles.values_mut());
+
+        for profile in profiles {
+            if let Some(api_key) = &mut profile.api_key {
+                *api_key = crate::config::redact(api_key);
+            }
+        }
+
+        let mut stdout = io::stdout();
+        config.write(&mut stdout, Format::from_path(&location))?;
+        stdout
+            .write_all(&[b'\n'])
+            .with_context(|| format!("failed to write new line into {}", "stdout".bold()))?;
+
+        Ok(())
+    }
+
+    /// Prefix the key with the profile's key, if a profile was passed.
+    fn profile_key(profile: Option<String>, key: String) -> anyhow::Result<String> {
+        match profile {
+            Some(profile) => Ok(format!(
+                "profiles.{}.{key}",
+                edit::check_profile_name(&profile)?
+            )),
+            None => Ok(key),
+        }
+    }
+
+    fn edit_location(config_path_override: Option<PathBuf>) -> PathBuf {
+        config_path_override.unwrap_or_else(|| crate::config::paths::location().to_path_buf())
+    }
+
+    pub fn get(
+        config_path_override: Option<PathBuf>,
+        profile: Option<String>,
+        key: String,
+    ) -> anyhow::Result<()> {
+        let location = edit_location(config_path_override);
+        let key = profile_key(profile, key)?;
+        let document = edit::load(&location, false)?;
+        let output = crate::output::get();
+
+        match edit::get(&document, &key)? {
+            Some(value) if !output.is_text() => output.emit(value)?,
+            Some(serde_json::Value::String(value)) => println!("{value}"),
+            Some(value) => println!(
+                "{}",
+                serde_json::to_string_pretty(value).context("failed to serialize value")?
+            ),
+            None => anyhow::bail!(
+                "the key {} is not set in the config at {}",
+                key.bold(),
+                location.display().bold()
+            ),
+        }
+
+        Ok(())
+    }
+
+    pub fn set(
+        config_path_override: Option<PathBuf>,
+        profile: Option<String>,
+        key: String,
+        value: String,
+    ) -> anyhow::Result<()> {
+        let location = edit_location(config_path_override);
+        let key = profile_key(profile, key)?;
+        let mut document = edit::load(&location, true)?;
+        let value = edit::parse_value(&key, &value)
+            .with_context(|| format!("invalid value for key {}", key.bold()))?;
+
+        edit::set(&mut document, &key, value)?;
+        edit::validate(&document).with_context(|| format!("failed to set key {}", key.bold()))?;
+        edit::save(&location, &document)?;
+
+        info!("set {} in {}", key.bold(), location.display().bold());
+
+        Ok(())
+    }
+
+    pub fn unset(
+        config_path_override: Option<PathBuf>,
+        profile: Option<String>,
+        key: String,
+    ) -> anyhow::Result<()> {
+        let location = edit_location(config_path_override);
+        let key = profile_key(profile, key)?;
+        let mut document = edit::load(&location, false)?;
+
+        if !edit::unset(&mut document, &key)? {
+            warn!(
+                "the key {} is not set in the config at {}",
+                key.bold(),
+                location.display().bold()
+            );
+            return Ok(());
+        }
+
+        edit::validate(&document)
+            .with_context(|| format!("failed to unset key {}", key.bold()))?;
+        edit::save(&location, &document)?;
+
+        info!("unset {} in {}", key.bold(), location.display().bold());
+
+        Ok(())
     }
 
+    pub fn convert(
+        config_path_override: Option<PathBuf>,
+        path: Option<PathBuf>,
+        to: Format,
+        keep: bool,
+    ) -> anyhow::Result<()> {
+        let location = path.unwrap_or_else(|| edit_location(config_path_override));
+        let from = Format::from_path(&location);
+
+        if from == to {
+            info!(
+                "the config at {} is already in the {} format",
+                location.display().bold(),
+                to.bold()
+            );
+            return Ok(());
+        }
+
+        let document = edit::load(&location, false)?;
+        let destination = location.with_extension(to.extension());
+
+        if destination.exists() {
+            anyhow::bail!("the path {} already exists", destination.display().bold())
+        }
+
+        edit::save_as(&destination, &document, crypto::is_encrypted_file(&location))
+            .with_context(|| format!("failed to convert the config to {}", to.bold()))?;
+        info!(
+            "converted the config at {} to {}",
+            location.display().bold(),
+            destination.display().bold()
+        );
+
+        if !keep {
+            fs::remove_file(&location).with_context(|| {
+                format!("failed to remove the path {}", location.display().bold())
+            })?;
+            info!("removed {}", location.display().bold());
+        }
+
+        Ok(())
+    }
+
+    pub fn migrate(
+        config_path_override: Option<PathBuf>,
+        path: Option<PathBuf>,
+    ) -> anyhow::Result<()> {
+        let location = path.unwrap_or_else(|| edit_location(config_path_override));
+        let mut document = edit::load(&location, false)?;
+        let version = match migrate::migrate(&mut document)? {
+            Some(version) => version,
+            None => {
+                info!(
+                    "the config at {} is already at the latest version {}",
+                    location.display().bold(),
+                    migrate::CURRENT_VERSION.bold()
+                );
+                return Ok(());
+            }
+        };
+        edit::validate(&document).context("the migrated config is not valid")?;
+
+        let file_name = location
+            .file_name()
+            .with_context(|| format!("the path {} has no file name", location.display().bold()))?
+            .to_string_lossy();
+        let backup = location.with_file_name(format!("{file_name}.v{version}.bak"));
+
+        if backup.exists() {
+            tip!("move or remove the existing backup first");
+            anyhow::bail!("the backup path {} already exists", backup.display().bold())
+        }
+
+        fs::copy(&location, &backup).with_context(|| {
+            format!(
+                "failed to back up the config at {} to {}",
+                location.display().bold(),
+                backup.display().bold()
+            )
+        })?;
+        edit::save(&location, &document)?;
+
+        info!(
+            "migrated the config at {} from version {} to {}",
+            location.display().bold(),
+            version.bold(),
+            migrate::CURRENT_VERSION.bold()
+        );
+        info!("the original was backed up to {}", backup.display().bold());
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn generate(
         config_path_override: Option<PathBuf>,
+        profile: Option<String>,
         path: Option<PathBuf>,
         api_key: String,
         engine_definition: Option<EngineDefinitionFromStrAdapter>,
         dump: bool,
         create: bool,
+        make_default: bool,
+        encrypt: bool,
+        format: Option<Format>,
     ) -> anyhow::Result<()> {
-        let mut writer = if dump {
-            FileOrStdout::Stdout(io::stdout())
-        } else {
-            let path = path
-                .or(config_path_override)
-                .unwrap_or_else(|| crate::config::paths::location().to_path_buf());
-
-            if let Some(parent) = path.parent() {
-                if !parent.exists() {
-                    alp::warn!(
-                        "parent directory {} does not exist, creating it",
-                        parent.display().bold()
-                    );
-                    fs::create_dir_all(parent).with_context(|| {
-                        format!(
-                            "failed to create parent directory {}",
-                            parent.display().bold()
-                        )
-                    })?;
-                }
+        if make_default && profile.is_none() {
+            anyhow::bail!(
+                "{} was passed, but no profile was specified with {}",
+                "--default".bold(),
+                "--profile".bold()
+            )
+        }
+
+        let path = match (path.or(config_path_override), format) {
+            (Some(path), _) => path,
+            (None, Some(format)) => crate::config::paths::location_with_format(format),
+            (None, None) => crate::config::paths::location().to_path_buf(),
+        };
+        let format = match format {
+            Some(format) if !dump && format != Format::from_path(&path) => {
+                tip!(
+                    "the format of a config file is picked from its extension; use a path ending with {}",
+                    format_args!(".{}", format.extension()).bold()
+                );
+                anyhow::bail!(
+                    "the format {} doesn't match the extension of path {}",
+                    format.bold(),
+                    path.display().bold()
+                )
+            }
+            Some(format) => format,
+            None => Format::from_path(&path),
+        };
+        let engine_definition = engine_definition.map(|engine_definition| engine_definition.0);
+        // keep an existing encrypted config encrypted when only a profile is updated
+        let encrypt = encrypt || (profile.is_some() && crypto::is_encrypted_file(&path));
+        // a profile is added to the existing file as a document rather than through `Config`, so
+        // that the keys this version doesn't know about are kept
+        if let Some(name) = &profile {
+            edit::check_profile_name(name)?;
+            let mut document = edit::load(&path, true).with_context(|| {
+                format!(
+                    "failed to load the existing config at {} to update profile {}",
+                    path.display().bold(),
+                    name.bold()
+                )
+            })?;
+            let key = |key: &str| format!("profiles.{name}.{key}");
+            edit::set(
+                &mut document,
+                &key("api_key"),
+                serde_json::Value::String(api_key),
+            )?;
+
+            if let Some(engine_definition) = engine_definition {
+                let engine_definition = serde_json::to_value(engine_definition)
+                    .context("failed to serialize engine definition")?;
+                edit::set(&mut document, &key("engine_definition"), engine_definition)?;
+            }
+
+            if make_default {
+                edit::set(
+                    &mut document,
+                    "default_profile",
+                    serde_json::Value::String(name.clone()),
+                )?;
             }
 
-            let mut builder = fs::OpenOptions::new().tap_mut(|this| {
-                this.write(true);
-            });
-
-            if create {
-                builder.create(true).truncate(true);
-            } else {
-                builder.create_new(true);
-            };
-
-            let result = builder.open(&path);
-
-            if let Err(error) = &result {
-                if let io::ErrorKind::AlreadyExists = error.kind() {
-                    let c_create = "-c/--create".bold();
-                    alp::tip!("as a precaution, writing a config file fails if it already exists. if this behavior is undesirable, pass the {c_create} argument in your command.");
-                    let command = env::args()
-                        .map(|argument| {
-                            if argument == api_key {
-                                "<API KEY REDACTED>".to_string()
-                            } else {
-                                argument
-                            }
-                        })
-                        .collect::<Vec<_>>()
-                        .join(" ");
-                    alp::tip!("short variant: {}", format_args!("{} -c", command).italic());
-                    alp::tip!(
-                        "long variant: {}",
-                        format_args!("{} --create", command).italic()
-                    );
+            edit::validate(&document)?;
+
+            // the other profiles and the top level values may have api keys of their own, so
+            // only the edited profile is printed
+            if dump {
+                let mut dumped = json!({ "version": migrate::CURRENT_VERSION });
+
+                let default_profile = edit::get(&document, "default_profile")?;
+
+                if default_profile.and_then(serde_json::Value::as_str) == Some(name) {
+                    dumped["default_profile"] = serde_json::Value::String(name.clone());
+                }
+
+                let edited = edit::get(&document, &format!("profiles.{name}"))?;
+                dumped["profiles"] = json!({ name.as_str(): edited });
+                let mut contents = format
+                    .serialize(&dumped)
+                    .context("failed to serialize config")?;
+
+                if encrypt {
+                    contents = crypto::encrypt(&contents, crypto::passphrase(true)?)?;
                 }
+
+                println!("{}", contents);
+
+                return Ok(());
+            }
+
+            // a passphrase which wasn't used for the file before is entered twice
+            if encrypt && !crypto::is_encrypted_file(&path) {
+                crypto::passphrase(true)?;
             }
 
-            let handle =
-                result.with_context(|| format!("failed to open path {}", path.display().bold()))?;
+            // the file is replaced as a whole, so that the other profiles aren't lost if writing it
+            // fails half way
+            edit::save_as(&path, &document, encrypt)?;
+
+            info!(
+                "wrote profile {} to config file at {}",
+                name.bold(),
+                path.display().bold()
+            );
+
+            return Ok(());
+        }
 
-            FileOrStdout::File { handle, path }
-        };
-        let engine_definition = engine_definition.map(|engine_definition| engine_definition.0);
         let config = Config {
-            api_key,
-            engine_definition: engine_definition.unwrap_or(Config::DEFAULT_ENGINE_DEFINITION),
+            base: Profile {
+                api_key: Some(api_key.clone()),
+                engine_definition: Some(
+                    engine_definition.unwrap_or(Config::DEFAULT_ENGINE_DEFINITION),
+                ),
+                ..Profile::default()
+            },
+            ..Config::default()
         };
 
-        config.write(&mut writer).with_context(|| match &writer {
-            FileOrStdout::File { path, .. } => {
-                format!("failed to write to file {}", path.display().bold())
-            }
-            FileOrStdout::Stdout(_) => format!("failed to write to {}", "stdout".bold()),
-        })?;
+        if !dump && !create && path.exists() {
+            let c_create = "-c/--create".bold();
+            tip!("as a precaution, writing a config file fails if it already exists. if this behavior is undesirable, pass the {c_create} argument in your command.");
+            tip!(
+                "alternatively, add a profile to the existing config file with {}",
+                "--profile <NAME>".bold()
+            );
+            let command = env::args()
+                .map(|argument| {
+                    if argument == api_key {
+                        "<API KEY REDACTED>".to_string()
+                    } else {
+                        argument
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+            tip!("short variant: {}", format_args!("{} -c", command).italic());
+            tip!(
+                "long variant: {}",
+                format_args!("{} --create", command).italic()
+            );
+            anyhow::bail!("the config file {} already exists", path.display().bold())
+        }
 
-        match writer {
-            FileOrStdout::File { path, .. } => {
-                alp::info!("generated config file at {}", path.display().bold())
-            }
-            FileOrStdout::Stdout(_) => {
-                // write an extra new line if stdout to prevent unterminated lines
-                writer.write_all(&[b'\n']).with_context(|| {
-                    format!("failed to write new line into {}", "stdout".bold())
-                })?;
+        let mut contents = Vec::new();
+
+        if encrypt {
+            config.write_encrypted(&mut contents, format, crypto::passphrase(true)?)?;
+        } else {
+            config.write(&mut contents, format)?;
+        }
+
+        if dump {
+            // write an extra new line to prevent unterminated lines
+            contents.push(b'\n');
+
+            return io::stdout()
+                .write_all(&contents)
+                .with_context(|| format!("failed to write to {}", "stdout".bold()));
+        }
+
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() && !parent.exists() {
+                warn!(
+                    "parent directory {} does not exist, creating it",
+                    parent.display().bold()
+                );
             }
         }
 
+        // the file is replaced as a whole, so that an existing file isn't left half written and
+        // gets the restricted permissions of a new one
+        crate::config::write_atomically(&path, &contents)?;
+        info!("generated config file at {}", path.display().bold());
+
         Ok(())
     }
 }
 
-use crate::{InfallibleFromStr, NonEmptyStringFromStrAdapter, Prompt, SynthTextTextCompletionMethod, TopKFromStrAdapter, TopPFromStrAdapter};
+pub use diagnostics::doctor;
+use crate::{ensure_stdin_read_once, Echo, InfallibleFromStr, Prompt, PromptSources, SynthTextTextCompletionMethod, TopKFromStrAdapter, TopPFromStrAdapter};
+use crate::retry::Policy;
+use crate::config::resolve::engine_definition_to_string;
+use crate::verbosity::{debug, info};
 use anyhow::Context;
+use text_completion::Parameters;
 use owo_colors::OwoColorize;
 
 pub async fn log_probabilities(
-    context: String,
-    NonEmptyStringFromStrAdapter(continuation): NonEmptyStringFromStrAdapter,
+    InfallibleFromStr(context): InfallibleFromStr<Prompt>,
+    InfallibleFromStr(continuation): InfallibleFromStr<Prompt>,
 ) -> anyhow::Result<()> {
-    alp::info!("the provided context was: '{context}'");
-    alp::info!("the predicted continuation was: '{}'", continuation.inner());
+    let output = crate::output::get();
+    let prompts = [context, continuation];
+    ensure_stdin_read_once(&prompts)?;
+
+    let [context, continuation] = prompts;
+    let context = context.into_string().context("failed to read the context")?;
+    let continuation = continuation
+        .into_string()
+        .context("failed to read the continuation")?;
 
-    let log_probabilities = crate::textsynth::engine()
-        .log_probabilities(context, continuation)
-        .await
-        .context("failed to connect to the textsynth api")?
-        .context("failed to get log probabilities")?;
+    if continuation.is_empty() {
+        anyhow::bail!("the continuation must not be empty")
+    }
+
+    if output.is_text() {
+        info!("the provided context was: '{context}'");
+        info!("the predicted continuation was: '{continuation}'");
+    }
+
+    let log_probabilities = Policy::resolved()?
+        .run("log probabilities", || {
+            debug!(
+                "log probabilities request: engine {}, api key {}, context of {} characters, continuation of {} characters",
+                engine_definition_to_string(&crate::textsynth::engine().definition),
+                text_completion::api_key_for_display(),
+                context.chars().count(),
+                continuation.chars().count()
+            );
+
+            let context = context.clone();
+            let continuation = continuation.clone();
 
-    alp::info!(
+            async move {
+                crate::textsynth::engine()
+                    .log_probabilities(&context, &continuation)
+                    .await
+                    .context("failed to get log probabilities")
+            }
+        })
+        .await?;
+
+    if !output.is_text() {
+        return output.emit(&serde_json::json!({
+            "log_probability": log_probabilities.log_probability(),
+            "probability": log_probabilities.log_probability().exp(),
+            "is_greedy": log_probabilities.is_greedy(),
+            "total_tokens": log_probabilities.total_tokens(),
+        }));
+    }
+
+    println!(
         "log probability: {}",
         log_probabilities.log_probability().bold()
     );
-    alp::info!("is greedy: {}", log_probabilities.is_greedy().bold());
-    alp::info!("total tokens: {}", log_probabilities.total_tokens().bold());
+    println!("is greedy: {}", log_probabilities.is_greedy().bold());
+    println!("total tokens: {}", log_probabilities.total_tokens().bold());
 
     Ok(())
 }
 
 pub async fn text_completion(
-    prompt: InfallibleFromStr<Prompt>,
+    prompt: PromptSources,
     max_tokens: Option<usize>,
     temperature: Option<f64>,
     top_k: Option<TopKFromStrAdapter>,
     top_p: Option<TopPFromStrAdapter>,
+    echo: Echo,
     method: SynthTextTextCompletionMethod,
 ) -> anyhow::Result<()> {
+    let (prompt, defaults) = prompt
+        .into_prompt()
+        .context("failed to put the prompt together")?;
+
     match method {
-        SynthTextTextCompletionMethod::Now { until } => {
-            text_completion::now(prompt, max_tokens, temperature, top_k, top_p, until).await
+        SynthTextTextCompletionMethod::Now {
+            until,
+            samples,
+            rank_by,
+        } => {
+            let parameters =
+                Parameters::new(max_tokens, temperature, top_k, top_p, until, defaults)?;
+            text_completion::now(prompt, parameters, echo, samples, rank_by).await
         }
-        SynthTextTextCompletionMethod::Stream => {
-            text_completion::stream(prompt, max_tokens, temperature, top_k, top_p).await
+        SynthTextTextCompletionMethod::Stream { until, resume } => {
+            let parameters =
+                Parameters::new(max_tokens, temperature, top_k, top_p, until, defaults)?;
+            text_completion::stream(prompt, parameters, resume, echo).await
         }
     }
 }
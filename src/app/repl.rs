@@ -0,0 +1,269 @@
+//! The interactive `repl` command, which keeps the client, the engine and the sampling parameters
+//! between completions.
+
+use super::text_completion::{self, Parameters};
+use crate::config::resolve::engine_definition_to_string;
+use crate::config::Defaults;
+use crate::verbosity::{error, info, warn};
+use crate::{
+    until_to_stop, Echo, EngineDefinitionFromStrAdapter, InfallibleFromStr, Prompt,
+    TopKFromStrAdapter, TopPFromStrAdapter,
+};
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+use std::fs;
+use std::str::FromStr;
+
+const PROMPT: &str = "synthtext> ";
+const CONTINUATION_PROMPT: &str = "... ";
+const HELP: &str = "\
+Type a prompt to complete it. End a line with \\ to continue the prompt on the next line, and
+start it with :: for a prompt which starts with a :.
+
+:temp [number]       set the temperature, or unset it without a number
+:topk [number]       set top_k, or unset it
+:topp [number]       set top_p, or unset it
+:max [number]        set the maximum number of tokens, or unset it
+:engine definition   switch to another engine definition, such as boris6b or my_engine,2048
+:until [string]      add a stop string, or remove them all without one; \\n is a new line
+:stream on|off       stream the completions as they're generated
+:logprob text        the log probability of the text following the last prompt
+:show                show the engine and the sampling parameters
+:reset               go back to the configured sampling parameters
+:help                show this help
+:quit                leave the repl; ctrl-d works too";
+
+/// What to do after a command.
+enum Flow {
+    Continue,
+    Quit,
+}
+
+struct Session {
+    parameters: Parameters,
+    stream: bool,
+    last_prompt: Option<String>,
+}
+
+impl Session {
+    fn new() -> anyhow::Result<Self> {
+        Ok(Self {
+            parameters: configured_parameters()?,
+            stream: false,
+            last_prompt: None,
+        })
+    }
+
+    async fn complete(&mut self, prompt: String) -> anyhow::Result<()> {
+        self.last_prompt = Some(prompt.clone());
+
+        if self.stream {
+            text_completion::stream(prompt, self.parameters.clone(), false, Echo::None).await
+        } else {
+            text_completion::now(prompt, self.parameters.clone(), Echo::None, 1, None).await
+        }
+    }
+
+    async fn command(&mut self, command: &str, argument: &str) -> anyhow::Result<Flow> {
+        match command {
+            "temp" | "temperature" => {
+                self.parameters.temperature = optional(argument, |argument| {
+                    argument.parse::<f64>().with_context(|| {
+                        format!("the given string {} wasn't a valid float", argument.bold())
+                    })
+                })?
+            }
+            "topk" => {
+                self.parameters.top_k = optional(argument, TopKFromStrAdapter::from_str)?
+                    .map(|top_k| top_k.value())
+            }
+            "topp" => {
+                self.parameters.top_p = optional(argument, TopPFromStrAdapter::from_str)?
+                    .map(|top_p| top_p.value())
+            }
+            "max" | "maxtokens" => {
+                self.parameters.max_tokens = optional(argument, |argument| {
+                    argument.parse::<usize>().with_context(|| {
+                        format!("the given string {} wasn't a valid number", argument.bold())
+                    })
+                })?
+            }
+            "engine" => {
+                let EngineDefinitionFromStrAdapter(engine_definition) = argument.parse()?;
+                crate::textsynth::set_engine(engine_definition);
+            }
+            "until" if argument.is_empty() => self.parameters.until.clear(),
+            "until" => {
+                let mut until = self.parameters.until.clone();
+                until.push(unescape(argument));
+                until_to_stop(&until)?;
+                self.parameters.until = until;
+            }
+            "stream" => {
+                self.stream = match argument {
+                    "on" => true,
+                    "off" => false,
+                    _ => anyhow::bail!("expected {} or {}", "on".bold(), "off".bold()),
+                }
+            }
+            "logprob" => {
+                let context = self
+                    .last_prompt
+                    .clone()
+                    .context("there is no prompt yet to be the context of the text")?;
+                super::log_probabilities(
+                    InfallibleFromStr(Prompt::String(context)),
+                    InfallibleFromStr(Prompt::String(unescape(argument))),
+                )
+                .await?;
+            }
+            "show" => self.show(),
+            "reset" => {
+                self.parameters = configured_parameters()?;
+                self.stream = false;
+            }
+            "help" | "h" | "?" => println!("{HELP}"),
+            "quit" | "q" | "exit" => return Ok(Flow::Quit),
+            _ => anyhow::bail!(
+                "unknown command {}; type {} for the list of commands",
+                format!(":{command}").bold(),
+                ":help".italic()
+            ),
+        }
+
+        Ok(Flow::Continue)
+    }
+
+    fn show(&self) {
+        fn or_unset(value: Option<impl ToString>) -> String {
+            value
+                .map(|value| value.to_string())
+                .unwrap_or_else(|| "(not set)".to_string())
+        }
+
+        println!(
+            "engine: {}",
+            engine_definition_to_string(&crate::textsynth::engine().definition).bold()
+        );
+        println!("max tokens: {}", or_unset(self.parameters.max_tokens).bold());
+        println!("temperature: {}", or_unset(self.parameters.temperature).bold());
+        println!("top k: {}", or_unset(self.parameters.top_k).bold());
+        println!("top p: {}", or_unset(self.parameters.top_p).bold());
+        println!("until: {}", format!("{:?}", self.parameters.until).bold());
+        println!("stream: {}", self.stream.bold());
+    }
+}
+
+/// The sampling parameters of the configuration.
+fn configured_parameters() -> anyhow::Result<Parameters> {
+    Parameters::new(None, None, None, None, Vec::new(), Defaults::default())
+}
+
+/// Parse the argument of a command, which unsets the value when it is empty.
+fn optional<T>(
+    argument: &str,
+    parse: impl FnOnce(&str) -> anyhow::Result<T>,
+) -> anyhow::Result<Option<T>> {
+    match argument {
+        "" => Ok(None),
+        argument => parse(argument).map(Some),
+    }
+}
+
+/// Turn `\n` and `\t` into a new line and a tab, since they can't be typed.
+fn unescape(text: &str) -> String {
+    text.replace("\\n", "\n").replace("\\t", "\t")
+}
+
+/// Read a prompt or a command. A line which ends with a backslash is continued on the next one.
+/// Returns nothing once standard input is closed.
+fn read(editor: &mut Editor<()>) -> anyhow::Result<Option<String>> {
+    let mut input = String::new();
+    let mut prompt = PROMPT;
+
+    loop {
+        match editor.readline(prompt) {
+            Ok(line) => match line.strip_suffix('\\') {
+                Some(line) => {
+                    input.push_str(line);
+                    input.push('\n');
+                    prompt = CONTINUATION_PROMPT;
+                }
+                None => {
+                    input.push_str(&line);
+                    return Ok(Some(input));
+                }
+            },
+
+            // ctrl-c throws away what was typed so far
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                prompt = PROMPT;
+            }
+            Err(ReadlineError::Eof) => return Ok(None),
+            Err(error) => return Err(error).context("failed to read a line"),
+        }
+    }
+}
+
+pub async fn run() -> anyhow::Result<()> {
+    let mut editor = Editor::<()>::new().context("failed to initialize line editing")?;
+    let history = crate::config::paths::history_location();
+
+    if history.exists() {
+        if let Err(error) = editor.load_history(&history) {
+            warn!(
+                "failed to load the history from {}: {}",
+                history.display().bold(),
+                error
+            );
+        }
+    }
+
+    let mut session = Session::new()?;
+    info!(
+        "type {} for the list of commands, or {} to leave",
+        ":help".italic(),
+        ":quit".italic()
+    );
+
+    while let Some(input) = read(&mut editor)? {
+        if input.trim().is_empty() {
+            continue;
+        }
+
+        editor.add_history_entry(input.as_str());
+
+        let result = match input.strip_prefix(':') {
+            Some(prompt) if prompt.starts_with(':') => {
+                session.complete(prompt.to_string()).await.map(|()| Flow::Continue)
+            }
+            Some(command) => {
+                let (command, argument) = command
+                    .split_once(char::is_whitespace)
+                    .map(|(command, argument)| (command, argument.trim()))
+                    .unwrap_or((command.trim(), ""));
+                session.command(command, argument).await
+            }
+            None => session.complete(input.clone()).await.map(|()| Flow::Continue),
+        };
+
+        match result {
+            Ok(Flow::Continue) => {}
+            Ok(Flow::Quit) => break,
+            Err(error) => error!("{:#}", error),
+        }
+    }
+
+    if let Some(directory) = history.parent() {
+        fs::create_dir_all(directory).with_context(|| {
+            format!("failed to create the directory {}", directory.display().bold())
+        })?;
+    }
+
+    editor
+        .save_history(&history)
+        .with_context(|| format!("failed to save the history to {}", history.display().bold()))
+}
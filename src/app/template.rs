@@ -0,0 +1,119 @@
+//! The `template` commands.
+
+use crate::template::{self, Template};
+use crate::verbosity::{info, tip, warn};
+use crate::{InfallibleFromStr, Prompt};
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use std::fs;
+
+pub fn list() -> anyhow::Result<()> {
+    let output = crate::output::get();
+    let mut templates = Vec::new();
+
+    for name in template::list()? {
+        match Template::load(&name) {
+            Ok(template) => templates.push(template),
+            Err(error) => warn!("{:#}", error),
+        }
+    }
+
+    if !output.is_text() {
+        let templates = templates
+            .iter()
+            .map(|template| {
+                serde_json::json!({
+                    "name": template.name,
+                    "description": template.front_matter.description,
+                    "variables": template.variables(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        return output.emit(&templates);
+    }
+
+    if templates.is_empty() {
+        info!(
+            "there are no templates in {}",
+            crate::config::paths::templates_directory().display().bold()
+        );
+        tip!("add one with {}", "synthtext template add NAME @path".italic());
+        return Ok(());
+    }
+
+    for template in templates {
+        match &template.front_matter.description {
+            Some(description) => println!("{}: {}", template.name.bold(), description),
+            None => println!("{}", template.name.bold()),
+        }
+    }
+
+    Ok(())
+}
+
+pub fn show(name: &str) -> anyhow::Result<()> {
+    let path = template::path(name)?;
+    let template = Template::load(name)?;
+    let output = crate::output::get();
+
+    if !output.is_text() {
+        return output.emit(&serde_json::json!({
+            "name": template.name,
+            "path": path,
+            "front_matter": template.front_matter,
+            "variables": template.variables(),
+            "body": template.body,
+        }));
+    }
+
+    let source = fs::read_to_string(&path)
+        .with_context(|| format!("failed to read template {}", path.display().bold()))?;
+    print!("{}", source);
+
+    Ok(())
+}
+
+pub fn add(
+    name: &str,
+    InfallibleFromStr(source): InfallibleFromStr<Prompt>,
+    force: bool,
+) -> anyhow::Result<()> {
+    let path = template::path(name)?;
+    let source = source.into_string().context("failed to read the template")?;
+    Template::parse(name, &source).with_context(|| format!("invalid template {}", name.bold()))?;
+
+    if path.exists() && !force {
+        tip!("pass {} to overwrite it", "--force".italic());
+        anyhow::bail!("the template {} already exists", name.bold())
+    }
+
+    let directory = crate::config::paths::templates_directory();
+    fs::create_dir_all(&directory).with_context(|| {
+        format!(
+            "failed to create the template directory {}",
+            directory.display().bold()
+        )
+    })?;
+    fs::write(&path, source)
+        .with_context(|| format!("failed to write template {}", path.display().bold()))?;
+
+    info!("added template {} at {}", name.bold(), path.display().bold());
+
+    Ok(())
+}
+
+pub fn remove(name: &str) -> anyhow::Result<()> {
+    let path = template::path(name)?;
+
+    if !path.exists() {
+        anyhow::bail!("there is no template named {}", name.bold())
+    }
+
+    fs::remove_file(&path)
+        .with_context(|| format!("failed to remove template {}", path.display().bold()))?;
+
+    info!("removed template {}", name.bold());
+
+    Ok(())
+}
@@ -1,126 +1,637 @@
-use crate::{InfallibleFromStr, Prompt, TopKFromStrAdapter, TopPFromStrAdapter};
+use crate::config::Defaults;
+use crate::output::{self, Output};
+use crate::retry::Policy;
+use crate::textsynth::{CompletionRequest, TextCompletion};
+use crate::verbosity::{debug, info, tip, warn};
+use crate::{until_to_stop, Echo, RankBy, TopKFromStrAdapter, TopPFromStrAdapter};
 use anyhow::Context;
-use futures::StreamExt;
+use futures::future;
 use owo_colors::OwoColorize;
+use serde::Serialize;
+use std::cmp::Ordering;
 use std::io::Write;
 
 use std::io;
-use tap::{Pipe, Tap, TryConv};
-use textsynth::prelude::{MaxTokens, Stop, TextCompletionBuilder};
+use tap::{Pipe, Tap};
+use textsynth::prelude::MaxTokens;
 
-fn common(
-    prompt: String,
-    max_tokens: Option<usize>,
-    temperature: Option<f64>,
-    top_k: Option<TopKFromStrAdapter>,
-    top_p: Option<TopPFromStrAdapter>,
-) -> anyhow::Result<TextCompletionBuilder<'static, 'static>> {
+/// The sampling parameters of a text completion, after the configured defaults were applied.
+#[derive(Clone, Serialize)]
+pub struct Parameters {
+    pub max_tokens: Option<usize>,
+    pub temperature: Option<f64>,
+    pub top_k: Option<u16>,
+    pub top_p: Option<f64>,
+    pub until: Vec<String>,
+}
+
+impl Parameters {
+    /// Fall back to the defaults of the template, then to the configured defaults, for the
+    /// sampling parameters which weren't passed on the command line.
+    pub fn new(
+        max_tokens: Option<usize>,
+        temperature: Option<f64>,
+        top_k: Option<TopKFromStrAdapter>,
+        top_p: Option<TopPFromStrAdapter>,
+        until: Vec<String>,
+        template_defaults: Defaults,
+    ) -> anyhow::Result<Self> {
+        let defaults = template_defaults.or(crate::config::resolved().defaults());
+        let until = if until.is_empty() {
+            defaults.until
+        } else {
+            until
+        };
+        until_to_stop(&until)?;
+
+        Ok(Self {
+            max_tokens: max_tokens.or(defaults.max_tokens),
+            temperature: temperature.or(defaults.temperature),
+            top_k: top_k.map(|top_k| top_k.value()).or(defaults.top_k),
+            top_p: top_p.map(|top_p| top_p.value()).or(defaults.top_p),
+            until,
+        })
+    }
+}
+
+/// The engine definition the way it is written in the config file.
+pub fn engine_json() -> serde_json::Value {
+    serde_json::to_value(&crate::textsynth::engine().definition).unwrap_or_default()
+}
+
+/// The redacted api key, or where it was taken from.
+pub fn api_key_for_display() -> String {
+    match &crate::config::resolved().api_key {
+        Some(api_key) => api_key.value.to_string(),
+        None => "(not set)".to_string(),
+    }
+}
+
+/// Check the parameters against the engine definition and create the text completion request.
+fn common(prompt: String, parameters: &Parameters) -> anyhow::Result<CompletionRequest> {
     let engine = crate::textsynth::engine();
-    let max_tokens: Option<MaxTokens> = match max_tokens {
-        Some(max_tokens) => MaxTokens::new(max_tokens, &engine.definition)
+
+    if let Some(max_tokens) = parameters.max_tokens {
+        MaxTokens::new(max_tokens, &engine.definition)
             .with_context(|| {
                 format!(
                     "the maximum number of tokens given, {}, is not enough to fit in the engine definition (maximum supported for current engine definition is {})",
                     max_tokens.bold(),
                     engine.definition.max_tokens().bold(),
                 )
-            })?
-            .pipe(Some),
-        None => None,
-    };
-    let top_k = top_k.map(|top_k| top_k.0);
-    let top_p = top_p.map(|top_p| top_p.0);
-    engine
-        .text_completion(prompt)
-        .tap_mut(|this| this.max_tokens = max_tokens)
-        .tap_mut(|this| this.temperature = temperature)
-        .tap_mut(|this| this.top_k = top_k)
-        .tap_mut(|this| this.top_p = top_p)
-        .pipe(Ok)
+            })?;
+    }
+
+    if let Some(top_k) = parameters.top_k {
+        TopKFromStrAdapter::try_from(top_k)?;
+    }
+
+    if let Some(top_p) = parameters.top_p {
+        TopPFromStrAdapter::try_from(top_p)?;
+    }
+
+    debug!(
+        "text completion request: engine {}, api key {}, prompt of {} characters, parameters {}",
+        engine_json(),
+        api_key_for_display(),
+        prompt.chars().count(),
+        serde_json::to_string(parameters).unwrap_or_default()
+    );
+
+    Ok(CompletionRequest {
+        prompt,
+        max_tokens: parameters.max_tokens,
+        temperature: parameters.temperature,
+        top_k: parameters.top_k,
+        top_p: parameters.top_p,
+        stop: parameters.until.clone(),
+    })
+}
+
+/// Make a text completion now, retrying it according to the retry settings.
+pub async fn complete(prompt: &str, parameters: &Parameters) -> anyhow::Result<TextCompletion> {
+    let text_completion = Policy::resolved()?
+        .run("text completion", || {
+            let request = common(prompt.to_string(), parameters);
+
+            async move {
+                crate::textsynth::engine()
+                    .text_completion(&request?)
+                    .await
+                    .context("failed to generate a text completion now")
+            }
+        })
+        .await?;
+
+    Ok(text_completion)
+}
+
+/// The log probability of the completion following the prompt, which is used to rank
+/// completions. An empty completion has none.
+async fn log_probability(prompt: &str, completion: &str) -> anyhow::Result<Option<f64>> {
+    if completion.is_empty() {
+        return Ok(None);
+    }
+
+    let log_probabilities = Policy::resolved()?
+        .run("log probabilities", || {
+            async move {
+                crate::textsynth::engine()
+                    .log_probabilities(prompt, completion)
+                    .await
+                    .context("failed to get log probabilities")
+            }
+        })
+        .await?;
+
+    Ok(Some(log_probabilities.log_probability()))
 }
 
 pub async fn now(
-    InfallibleFromStr(prompt): InfallibleFromStr<Prompt>,
-    max_tokens: Option<usize>,
-    temperature: Option<f64>,
-    top_k: Option<TopKFromStrAdapter>,
-    top_p: Option<TopPFromStrAdapter>,
-    until: Vec<String>,
+    prompt: String,
+    parameters: Parameters,
+    echo: Echo,
+    samples: usize,
+    rank_by: Option<RankBy>,
 ) -> anyhow::Result<()> {
-    let until = if until.is_empty() {
-        None
-    } else {
-        until
-            .as_slice()
-            .try_conv::<Stop>()
-            .with_context(|| {
-                format!(
-                    "passed overflowing {} argument; expected <= 5 items but got {}",
-                    "until".bold(),
-                    until.len()
-                )
-            })
-            .map(Some)?
-    };
-    let prompt = prompt.into_string().context("failed to parse prompt into string")?;
-    let builder = common(prompt.clone(), max_tokens, temperature, top_k, top_p)?;
-    let text_completion = match until {
-        Some(until) => builder
-            .now_until(until)
-            .await
-            .context("failed to connect to the textsynth api")?
-            .context("failed to generate a text completion now")?,
-        None => builder
-            .now()
-            .await
-            .context("failed to connect to the textsynth api")?
-            .context("failed to generate a text completion now")?,
+    if samples == 0 {
+        anyhow::bail!("at least one sample must be generated")
+    }
+
+    let text_completions =
+        future::try_join_all((0..samples).map(|_| complete(&prompt, &parameters))).await?;
+    let samples = match rank_by {
+        Some(RankBy::LogProbability) => {
+            let scores = text_completions
+                .iter()
+                .map(|text_completion| log_probability(&prompt, text_completion.text()))
+                .pipe(future::try_join_all)
+                .await?;
+
+            // the best score first, and the completions without one last
+            text_completions
+                .into_iter()
+                .zip(scores)
+                .collect::<Vec<_>>()
+                .tap_mut(|samples| {
+                    samples.sort_by(|(_, a), (_, b)| match (a, b) {
+                        (Some(a), Some(b)) => b.total_cmp(a),
+                        (Some(_), None) => Ordering::Less,
+                        (None, Some(_)) => Ordering::Greater,
+                        (None, None) => Ordering::Equal,
+                    })
+                })
+        }
+        None => text_completions
+            .into_iter()
+            .map(|text_completion| (text_completion, None))
+            .collect(),
     };
-    print!("{}", prompt);
+    let output = output::get();
+
+    if !output.is_text() {
+        let documents = samples
+            .iter()
+            .map(|(text_completion, score)| {
+                let mut document = serde_json::json!({
+                    "text": text_completion.text(),
+                    "truncated_prompt": text_completion.truncated_prompt(),
+                    "total_tokens": text_completion.total_tokens(),
+                    "engine": engine_json(),
+                    "parameters": parameters,
+                });
+
+                if rank_by.is_some() {
+                    document["log_probability"] = (*score).into();
+                }
+
+                document
+            })
+            .collect::<Vec<_>>();
+
+        // a single completion is written the same way as before there could be many
+        return match output {
+            Output::Ndjson => documents
+                .iter()
+                .try_for_each(|document| output.emit(document)),
+            _ if documents.len() == 1 && rank_by.is_none() => output.emit(&documents[0]),
+            _ => output.emit(&documents),
+        };
+    }
+
+    let headers = samples.len() > 1 || rank_by.is_some();
 
-    println!("{}", text_completion.text());
+    for (index, (text_completion, score)) in samples.iter().enumerate() {
+        if headers {
+            match score {
+                Some(score) => println!(
+                    "{}",
+                    format!("--- sample {} (log probability {:.4}) ---", index + 1, score).bold()
+                ),
+                None => println!("{}", format!("--- sample {} ---", index + 1).bold()),
+            }
+        }
 
-    if text_completion.truncated_prompt() {
-        alp::warn!("prompt was truncated; the prompt was too large compared to the engine definition's maximum context length");
-        alp::tip!(
+        echo.write(&prompt);
+
+        println!("{}", text_completion.text());
+    }
+
+    if samples
+        .iter()
+        .any(|(text_completion, _)| text_completion.truncated_prompt())
+    {
+        warn!("prompt was truncated; the prompt was too large compared to the engine definition's maximum context length");
+        tip!(
             "try shortening your prompt to fit in the engine definition's maximum context length"
         );
     }
 
-    if let Some(total_tokens) = text_completion.total_tokens() {
-        alp::info!("total tokens used: {}", total_tokens.bold());
+    let total_tokens = samples
+        .iter()
+        .filter_map(|(text_completion, _)| text_completion.total_tokens())
+        .reduce(|total, total_tokens| total + total_tokens);
+
+    if let Some(total_tokens) = total_tokens {
+        info!("total tokens used: {}", total_tokens.bold());
+    }
+
+    Ok(())
+}
+
+/// Finds stop strings in streamed text, even when they're split across chunks. Streams are stopped
+/// on this side rather than by the api, so that it's known whether a stop string was found.
+struct StopFinder {
+    stops: Vec<String>,
+
+    /// Text which could be the start of a stop string, and is held back until it's known not to be.
+    pending: String,
+}
+
+impl StopFinder {
+    fn new(stops: Vec<String>) -> Self {
+        Self {
+            stops: stops.into_iter().filter(|stop| !stop.is_empty()).collect(),
+            pending: String::new(),
+        }
+    }
+
+    /// Add a chunk, returning the text which can be printed and whether a stop string was found.
+    /// The text before the stop string is returned, but not the stop string itself.
+    fn push(&mut self, chunk: &str) -> (String, bool) {
+        self.pending.push_str(chunk);
+
+        let found = self
+            .stops
+            .iter()
+            .filter_map(|stop| self.pending.find(stop.as_str()))
+            .min();
+
+        if let Some(index) = found {
+            let text = self.pending[..index].to_string();
+            self.pending.clear();
+            return (text, true);
+        }
+
+        let held = self
+            .stops
+            .iter()
+            .map(|stop| partial_match(&self.pending, stop))
+            .max()
+            .unwrap_or(0);
+        let text = self
+            .pending
+            .drain(..self.pending.len() - held)
+            .collect::<String>();
+
+        (text, false)
+    }
+
+    /// The text which was held back, once the stream is over.
+    fn finish(&mut self) -> String {
+        std::mem::take(&mut self.pending)
     }
+}
+
+/// The length of the longest beginning of the stop string which the text ends with.
+fn partial_match(text: &str, stop: &str) -> usize {
+    (1..stop.len())
+        .rev()
+        .filter(|&length| stop.is_char_boundary(length))
+        .find(|&length| text.ends_with(&stop[..length]))
+        .unwrap_or(0)
+}
+
+/// Write the text which was held back once the stream is over.
+fn finish(
+    stop_finder: &mut StopFinder,
+    output: Output,
+    completion: &mut String,
+) -> anyhow::Result<()> {
+    let text = stop_finder.finish();
+    write_chunk(output, &text)?;
+    completion.push_str(&text);
 
     Ok(())
 }
 
+/// Write a piece of streamed text, as is or as a line of ndjson.
+fn write_chunk(output: Output, text: &str) -> anyhow::Result<()> {
+    match output {
+        Output::Text => {
+            print!("{}", text);
+            io::stdout().flush().context("failed to flush stdout")
+        }
+        Output::Ndjson if !text.is_empty() => {
+            output.emit(&serde_json::json!({ "type": "chunk", "text": text }))
+        }
+        // a single json document is written once the stream is over
+        Output::Ndjson | Output::Json => Ok(()),
+    }
+}
+
+/// The number of tokens of the text generated so far, as counted by the api. When it can't be
+/// tokenized, each chunk is counted as a token instead, since the api sends about one per chunk.
+async fn generated_tokens(generated: &str, chunks: usize) -> usize {
+    match crate::textsynth::engine().tokenize(generated).await {
+        Ok(tokens) => tokens.len(),
+        Err(error) => {
+            debug!(
+                "failed to count the tokens generated so far, counting {} chunks instead: {:#}",
+                chunks, error
+            );
+            chunks
+        }
+    }
+}
+
 pub async fn stream(
-    InfallibleFromStr(prompt): InfallibleFromStr<Prompt>,
-    max_tokens: Option<usize>,
-    temperature: Option<f64>,
-    top_k: Option<TopKFromStrAdapter>,
-    top_p: Option<TopPFromStrAdapter>,
+    prompt: String,
+    parameters: Parameters,
+    resume: bool,
+    echo: Echo,
 ) -> anyhow::Result<()> {
-    let prompt = prompt.into_string().context("failed to parse prompt into string")?;
-    let mut stream = common(prompt.clone(), max_tokens, temperature, top_k, top_p)?
-        .stream()
-        .await
-        .context("failed to connect to the textsynth api")?;
-    let mut stdout = io::stdout();
-    print!("{}", prompt);
-    stdout.flush().context("failed to flush stdout")?;
+    // the number of tokens which are left after an interruption can't be known otherwise
+    if resume && parameters.max_tokens.is_none() {
+        tip!(
+            "pass {} or set {} in the config",
+            "--max-tokens".italic(),
+            "max_tokens".italic()
+        );
+        anyhow::bail!(
+            "{} needs a maximum number of tokens to generate",
+            "--resume".bold()
+        )
+    }
+
+    let mut stop_finder = StopFinder::new(parameters.until.clone());
+    let output = output::get();
+    let policy = Policy::resolved()?;
+    let mut echoed = false;
+    let mut stopped = false;
+
+    // the text which was already received, and how many chunks it took
+    let mut generated = String::new();
+    let mut chunks = 0;
+
+    // the text which was written, which leaves out the stop string
+    let mut completion = String::new();
+    let mut attempt = 1;
+
+    loop {
+        let chunks_before = chunks;
+        let result = async {
+            // when resuming, continue from where the previous stream stopped instead of starting
+            // over, producing only the tokens which are left
+            let max_tokens = match parameters.max_tokens {
+                Some(max_tokens) if chunks > 0 => {
+                    Some(max_tokens.saturating_sub(generated_tokens(&generated, chunks).await))
+                }
+                max_tokens => max_tokens,
+            };
+
+            if chunks > 0 && max_tokens == Some(0) {
+                return finish(&mut stop_finder, output, &mut completion);
+            }
+
+            let parameters = Parameters {
+                max_tokens,
+                ..parameters.clone()
+            };
+            let request = CompletionRequest {
+                stop: Vec::new(),
+                ..common(format!("{prompt}{generated}"), &parameters)?
+            };
+            let mut stream = crate::textsynth::engine()
+                .stream(&request)
+                .await
+                .context("failed to start streaming a text completion")?;
+
+            if !echoed && output.is_text() {
+                echo.write(&prompt);
+                io::stdout().flush().context("failed to flush stdout")?;
+            }
+
+            echoed = true;
+
+            while let Some(text_completion) = stream.next().await? {
+                let (text, found) = stop_finder.push(text_completion.text());
+                write_chunk(output, &text)?;
+                completion.push_str(&text);
+                generated.push_str(text_completion.text());
+                chunks += 1;
+
+                // dropping the stream cancels the request
+                if found {
+                    stopped = true;
+                    return Ok(());
+                }
+            }
+
+            finish(&mut stop_finder, output, &mut completion)
+        }
+        .await;
 
-    while let Some(text_completion) = stream.next().await {
-        let text_completion = text_completion
-            .context("failed to connect to textsynth api")?
-            .context("failed to parse output from textsynth api to json")?
-            .context("failed to get next text completion")?;
+        let error = match result {
+            Ok(()) => break,
+            Err(error) => error,
+        };
 
-        print!("{}", text_completion.text());
-        stdout.flush().context("failed to flush stdout")?;
+        if !generated.is_empty() && !resume {
+            // what was already written to stdout can't be taken back, so don't start over
+            tip!(
+                "pass {} to continue an interrupted stream where it stopped",
+                "--resume".italic()
+            );
+            return Err(error);
+        }
+
+        // a stream which made progress before failing gets a fresh set of attempts
+        if chunks > chunks_before {
+            attempt = 1;
+        }
+
+        match policy.delay(attempt, &error) {
+            Some(delay) => {
+                policy.wait("text completion stream", attempt, &error, delay).await;
+                attempt += 1;
+
+                if chunks > 0 {
+                    debug!("resuming the stream after {} chunks", chunks);
+                }
+            }
+            None => return Err(error),
+        }
     }
 
-    println!();
+    if output.is_text() {
+        println!();
+        return Ok(());
+    }
 
-    Ok(())
+    let mut summary = serde_json::json!({
+        "text": completion,
+        "stopped": stopped,
+        "chunks": chunks,
+        "engine": engine_json(),
+        "parameters": parameters,
+    });
+
+    if output == Output::Ndjson {
+        summary["type"] = "summary".into();
+    }
+
+    output.emit(&summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{partial_match, StopFinder};
+
+    fn stop_finder(stops: &[&str]) -> StopFinder {
+        StopFinder::new(stops.iter().map(ToString::to_string).collect())
+    }
+
+    #[test]
+    fn partial_match_is_the_longest_beginning_of_the_stop() {
+        assert_eq!(partial_match("abc", "cde"), 1);
+        assert_eq!(partial_match("abcd", "cde"), 2);
+        assert_eq!(partial_match("abc", "xyz"), 0);
+        assert_eq!(partial_match("", "xyz"), 0);
+    }
+
+    #[test]
+    fn partial_match_does_not_count_the_whole_stop() {
+        assert_eq!(partial_match("abc", "bc"), 0);
+    }
+
+    #[test]
+    fn partial_match_ends_on_character_boundaries() {
+        // é is two bytes, and neither of them on its own is a beginning of the stop
+        assert_eq!(partial_match("caf\u{e9}", "\u{e9}!"), 2);
+        assert_eq!(partial_match("caf\u{e8}", "\u{e9}!"), 0);
+    }
+
+    #[test]
+    fn text_without_stops_is_passed_through() {
+        let mut stop_finder = stop_finder(&[]);
+
+        assert_eq!(stop_finder.push("hello"), ("hello".to_string(), false));
+        assert_eq!(stop_finder.push(" world"), (" world".to_string(), false));
+        assert_eq!(stop_finder.finish(), "");
+    }
+
+    #[test]
+    fn empty_stops_are_ignored() {
+        let mut stop_finder = stop_finder(&[""]);
+
+        assert_eq!(stop_finder.push("hello"), ("hello".to_string(), false));
+    }
+
+    #[test]
+    fn stop_in_a_single_chunk() {
+        let mut stop_finder = stop_finder(&["\n\n"]);
+
+        assert_eq!(stop_finder.push("abc\n\ndef"), ("abc".to_string(), true));
+    }
+
+    #[test]
+    fn stop_split_across_chunks() {
+        let mut stop_finder = stop_finder(&["\n\n"]);
+
+        assert_eq!(stop_finder.push("abc\n"), ("abc".to_string(), false));
+        assert_eq!(stop_finder.push("\ndef"), (String::new(), true));
+    }
+
+    #[test]
+    fn stop_split_across_many_chunks() {
+        let mut stop_finder = stop_finder(&["STOP"]);
+
+        assert_eq!(stop_finder.push("aS"), ("a".to_string(), false));
+        assert_eq!(stop_finder.push("T"), (String::new(), false));
+        assert_eq!(stop_finder.push("O"), (String::new(), false));
+        assert_eq!(stop_finder.push("Pb"), (String::new(), true));
+    }
+
+    #[test]
+    fn held_text_which_is_not_a_stop_is_released() {
+        let mut stop_finder = stop_finder(&["STOP"]);
+
+        assert_eq!(stop_finder.push("aST"), ("a".to_string(), false));
+        assert_eq!(stop_finder.push("x"), ("STx".to_string(), false));
+    }
+
+    #[test]
+    fn overlapping_prefixes() {
+        // after "aa", another "a" could still be followed by the "b" of the stop
+        let mut stop_finder = stop_finder(&["aab"]);
+
+        assert_eq!(stop_finder.push("a"), (String::new(), false));
+        assert_eq!(stop_finder.push("a"), (String::new(), false));
+        assert_eq!(stop_finder.push("a"), ("a".to_string(), false));
+        assert_eq!(stop_finder.push("b"), (String::new(), true));
+    }
+
+    #[test]
+    fn the_earliest_of_several_stops_is_found() {
+        let mut stop_finder = stop_finder(&["b", "a"]);
+
+        assert_eq!(stop_finder.push("xab"), ("x".to_string(), true));
+    }
+
+    #[test]
+    fn the_longest_partial_match_of_several_stops_is_held() {
+        let mut stop_finder = stop_finder(&["xy", "abc"]);
+
+        assert_eq!(stop_finder.push("1ab"), ("1".to_string(), false));
+        assert_eq!(stop_finder.push("c"), (String::new(), true));
+    }
+
+    #[test]
+    fn multibyte_stop() {
+        let mut stop_finder = stop_finder(&["\u{e9}!"]);
+
+        assert_eq!(stop_finder.push("caf\u{e9}"), ("caf".to_string(), false));
+        assert_eq!(stop_finder.push("?"), ("\u{e9}?".to_string(), false));
+        assert_eq!(stop_finder.push("\u{e9}"), (String::new(), false));
+        assert_eq!(stop_finder.push("!"), (String::new(), true));
+    }
+
+    #[test]
+    fn multibyte_text_around_an_ascii_stop() {
+        let mut stop_finder = stop_finder(&["--"]);
+
+        assert_eq!(stop_finder.push("\u{1f600}-"), ("\u{1f600}".to_string(), false));
+        assert_eq!(stop_finder.push("-\u{1f600}"), (String::new(), true));
+    }
+
+    #[test]
+    fn held_text_is_flushed_at_the_end() {
+        let mut stop_finder = stop_finder(&["END"]);
+
+        assert_eq!(stop_finder.push("abcE"), ("abc".to_string(), false));
+        assert_eq!(stop_finder.push("N"), (String::new(), false));
+        assert_eq!(stop_finder.finish(), "EN");
+        assert_eq!(stop_finder.finish(), "");
+    }
 }
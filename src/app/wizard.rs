@@ -0,0 +1,250 @@
+//! The interactive `config init` wizard.
+
+use crate::config::edit;
+use crate::verbosity::{info, tip, warn};
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use serde_json::Value;
+use std::io::Write;
+use std::path::PathBuf;
+use std::{fmt, io};
+use textsynth::prelude::{CustomEngineDefinition, EngineDefinition};
+
+fn ask(question: impl fmt::Display) -> anyhow::Result<String> {
+    let mut stderr = io::stderr();
+    write!(stderr, "{} ", question).context("failed to write to stderr")?;
+    stderr.flush().context("failed to flush stderr")?;
+
+    let mut answer = String::new();
+    let read = io::stdin()
+        .read_line(&mut answer)
+        .context("failed to read from standard input")?;
+
+    if read == 0 {
+        anyhow::bail!("standard input was closed before an answer was given")
+    }
+
+    Ok(answer.trim().to_string())
+}
+
+fn ask_api_key(existing: bool) -> anyhow::Result<Option<String>> {
+    let question = if existing {
+        "api key (leave empty to keep the existing one):"
+    } else {
+        "api key:"
+    };
+
+    loop {
+        let api_key = rpassword::prompt_password(format!("{} ", question.bold()))
+            .context("failed to read the api key")?;
+        let api_key = api_key.trim();
+
+        match (api_key.is_empty(), existing) {
+            (false, _) => return Ok(Some(api_key.to_string())),
+            (true, true) => return Ok(None),
+            (true, false) => warn!("the api key can't be empty"),
+        }
+    }
+}
+
+fn ask_custom_engine_definition() -> anyhow::Result<EngineDefinition> {
+    let id = loop {
+        let id = ask("engine id:".bold())?;
+
+        if id.is_empty() {
+            warn!("the engine id can't be empty");
+        } else {
+            break id;
+        }
+    };
+    let max_tokens = loop {
+        match ask("maximum number of tokens:".bold())?.parse::<usize>() {
+            Ok(max_tokens) => break max_tokens,
+            Err(_) => warn!("the maximum number of tokens must be a valid number"),
+        }
+    };
+
+    Ok(EngineDefinition::Custom(CustomEngineDefinition::new(
+        id, max_tokens,
+    )))
+}
+
+fn ask_engine_definition() -> anyhow::Result<EngineDefinition> {
+    let known = [
+        ("GptJ6B", EngineDefinition::GptJ6B),
+        ("Boris6B", EngineDefinition::Boris6B),
+        ("FairseqGpt13B", EngineDefinition::FairseqGpt13B),
+    ];
+
+    // the menu is part of the prompt, so it's shown even with --quiet
+    eprintln!("{}", "choose an engine definition:".bold());
+
+    for (index, (name, _)) in known.iter().enumerate() {
+        eprintln!("  {}) {}", (index + 1).bold(), name);
+    }
+
+    eprintln!(
+        "  {}) custom id and maximum number of tokens",
+        (known.len() + 1).bold()
+    );
+
+    loop {
+        let answer = ask(format_args!(
+            "{} {}",
+            "engine definition:".bold(),
+            "[1]".italic()
+        ))?;
+        let choice = if answer.is_empty() {
+            1
+        } else {
+            match answer.parse::<usize>() {
+                Ok(choice) => choice,
+                Err(_) => {
+                    warn!("expected a number between 1 and {}", known.len() + 1);
+                    continue;
+                }
+            }
+        };
+
+        match choice {
+            choice if (1..=known.len()).contains(&choice) => {
+                return Ok(known[choice - 1].1.clone())
+            }
+            choice if choice == known.len() + 1 => return ask_custom_engine_definition(),
+            _ => warn!("expected a number between 1 and {}", known.len() + 1),
+        }
+    }
+}
+
+/// Compute a line based diff between two texts, using the longest common subsequence.
+fn diff<'a>(old: &'a str, new: &'a str) -> Vec<(char, &'a str)> {
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+    let mut lengths = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = if old[i] == new[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            lines.push((' ', old[i]));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            lines.push(('-', old[i]));
+            i += 1;
+        } else {
+            lines.push(('+', new[j]));
+            j += 1;
+        }
+    }
+
+    lines.extend(old[i..].iter().map(|line| ('-', *line)));
+    lines.extend(new[j..].iter().map(|line| ('+', *line)));
+    lines
+}
+
+fn show_diff(old: &Value, new: &Value) -> anyhow::Result<()> {
+    let old = serde_json::to_string_pretty(&edit::redacted(old))
+        .context("failed to serialize the existing config")?;
+    let new = serde_json::to_string_pretty(&edit::redacted(new))
+        .context("failed to serialize the new config")?;
+
+    for (tag, line) in diff(&old, &new) {
+        match tag {
+            '-' => eprintln!("{}", format_args!("- {line}").red()),
+            '+' => eprintln!("{}", format_args!("+ {line}").green()),
+            _ => eprintln!("{}", format_args!("  {line}").dimmed()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Interactively ask for the api key and engine definition, then write them to the config file
+/// (or to the profile, if one was passed). The rest of an existing config file is kept as is.
+pub fn init(
+    config_path_override: Option<PathBuf>,
+    profile: Option<String>,
+    path: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    let location = path
+        .or(config_path_override)
+        .unwrap_or_else(|| crate::config::paths::location().to_path_buf());
+    let exists = location.exists();
+    let existing = edit::load(&location, true)?;
+    let prefix = match &profile {
+        Some(profile) => format!("profiles.{}.", edit::check_profile_name(profile)?),
+        None => String::new(),
+    };
+    let has_api_key = edit::get(&existing, &format!("{prefix}api_key"))?.is_some();
+
+    match &profile {
+        Some(profile) => info!(
+            "configuring profile {} in {}",
+            profile.bold(),
+            location.display().bold()
+        ),
+        None => info!("configuring {}", location.display().bold()),
+    }
+
+    let api_key = ask_api_key(has_api_key)?;
+    let engine_definition = ask_engine_definition()?;
+    let mut document = existing.clone();
+
+    if let Some(api_key) = api_key {
+        edit::set(&mut document, &format!("{prefix}api_key"), Value::String(api_key))?;
+    }
+
+    let engine_definition =
+        serde_json::to_value(engine_definition).context("failed to serialize engine definition")?;
+    edit::set(
+        &mut document,
+        &format!("{prefix}engine_definition"),
+        engine_definition,
+    )?;
+    edit::validate(&document)?;
+
+    if exists {
+        if document == existing {
+            info!("nothing changed, not writing the config file");
+            return Ok(());
+        }
+
+        eprintln!("the config file already exists; these changes will be made:");
+        show_diff(&existing, &document)?;
+
+        let answer = ask(format_args!("{} {}", "write these changes?".bold(), "[y/N]".italic()))?;
+
+        if !matches!(answer.to_lowercase().as_str(), "y" | "yes") {
+            info!("not writing the config file");
+            return Ok(());
+        }
+    }
+
+    edit::save(&location, &document)?;
+
+    info!("wrote config file at {}", location.display().bold());
+
+    if document.get("default_profile").is_none() {
+        if let Some(profile) = profile {
+            tip!(
+                "make {} the default profile with {}",
+                profile.bold(),
+                format_args!("synthtext config set default_profile {profile}").italic()
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -1,13 +1,22 @@
 use std::convert::Infallible;
 use std::io;
-use std::io::Read;
+use std::fs;
+use std::io::{IsTerminal, Read};
+use crate::app::batch::InputFormat;
+use crate::config::format::Format;
+use crate::config::resolve::Overrides;
+use crate::config::{Connection, Defaults, Retry};
+use crate::output::Output;
+use crate::template::Invocation;
 use anyhow::Context;
 use clap::Parser;
 use owo_colors::OwoColorize;
 use std::path::PathBuf;
 use std::str::FromStr;
-use tap::Pipe;
-use textsynth::prelude::{CustomEngineDefinition, EngineDefinition, NonEmptyString, TopK, TopP};
+use tap::{Pipe, TryConv};
+use textsynth::prelude::{
+    CustomEngineDefinition, EngineDefinition, Stop, TopK, TopP,
+};
 
 /// A program which wraps the TextSynth API.
 #[derive(Debug, Parser)]
@@ -16,45 +25,138 @@ pub struct SynthText {
     #[clap(short, long)]
     pub config: Option<PathBuf>,
 
+    /// Use the specified profile from the configuration file instead of the default profile.
+    #[clap(long)]
+    pub profile: Option<String>,
+
+    /// Refuse to load a configuration file which other users can access, instead of only warning
+    /// about it.
+    #[clap(long)]
+    pub strict_permissions: bool,
+
+    /// Only show warnings and errors on stderr.
+    #[clap(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Show what is being done and how long it took on stderr. Pass twice to also show every
+    /// request's parameters, with the api key redacted, and every attempt.
+    #[clap(short, long, parse(from_occurrences))]
+    pub verbose: u8,
+
+    /// How to write results: text, json, or ndjson (one json document per line).
+    #[clap(short, long, default_value = "text")]
+    pub output: Output,
+
+    /// Connect to the textsynth api at the specified base url, such as a caching gateway.
+    #[clap(long)]
+    pub base_url: Option<String>,
+
+    /// Connect to the textsynth api through the specified http(s) proxy.
+    #[clap(long)]
+    pub proxy: Option<String>,
+
+    /// How many seconds a request may take. For streams, how many seconds to wait for the api to
+    /// start answering, and then for each chunk.
+    #[clap(long)]
+    pub timeout: Option<f64>,
+
+    /// How many seconds to wait for a connection to the textsynth api to be established.
+    #[clap(long)]
+    pub connect_timeout: Option<f64>,
+
+    /// Trust the certificate authorities in the specified pem file, in addition to the system
+    /// ones.
+    #[clap(long)]
+    pub ca_bundle: Option<PathBuf>,
+
+    /// How many times a request is made at most, including the first time. 1 disables retrying.
+    #[clap(long)]
+    pub max_attempts: Option<u32>,
+
+    /// How many seconds to wait before the first retry. The delay doubles after every attempt.
+    #[clap(long)]
+    pub retry_delay: Option<f64>,
+
+    /// How many seconds to wait at most between two attempts.
+    #[clap(long)]
+    pub retry_max_delay: Option<f64>,
+
+    /// The fraction of the delay between two attempts, from 0 to 1, which is randomized.
+    #[clap(long)]
+    pub retry_jitter: Option<f64>,
+
     #[clap(subcommand)]
     pub action: SynthTextAction,
 }
 
-#[derive(Debug)]
-pub struct NonEmptyStringFromStrAdapter(pub NonEmptyString);
+impl SynthText {
+    /// The values passed on the command line which take part in the resolution of the
+    /// configuration.
+    pub fn overrides(&self) -> Overrides {
+        Overrides {
+            profile: self.profile.clone(),
+            engine_definition: self.action.engine_override().cloned(),
+            connection: Connection {
+                base_url: self.base_url.clone(),
+                proxy: self.proxy.clone(),
+                timeout: self.timeout,
+                connect_timeout: self.connect_timeout,
+                ca_bundle: self.ca_bundle.clone(),
+            },
+            retry: Retry {
+                max_attempts: self.max_attempts,
+                base_delay: self.retry_delay,
+                max_delay: self.retry_max_delay,
+                jitter: self.retry_jitter,
+            },
+        }
+    }
+}
 
-impl FromStr for NonEmptyStringFromStrAdapter {
-    type Err = anyhow::Error;
+#[derive(Debug, Clone)]
+pub struct TopKFromStrAdapter(u16);
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        NonEmptyString::new(s.into())
-            .context("given string was empty")
-            .map(Self)
+impl TopKFromStrAdapter {
+    /// The number, which is known to be in bounds.
+    pub fn value(&self) -> u16 {
+        self.0
     }
 }
 
-#[derive(Debug)]
-pub struct TopKFromStrAdapter(pub TopK);
-
 impl FromStr for TopKFromStrAdapter {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         s.parse::<u16>()
             .with_context(|| format!("the given string {} wasn't a valid number", s.bold()))?
-            .pipe(TopK::new)
+            .pipe(Self::try_from)
+    }
+}
+
+impl TryFrom<u16> for TopKFromStrAdapter {
+    type Error = anyhow::Error;
+
+    fn try_from(top_k: u16) -> Result<Self, Self::Error> {
+        TopK::new(top_k)
             .with_context(|| {
                 format!(
                     "the number {} wasn't in the required bound of 0..=1000",
-                    s.bold()
+                    top_k.bold()
                 )
             })
-            .map(Self)
+            .map(|_| Self(top_k))
     }
 }
 
-#[derive(Debug)]
-pub struct TopPFromStrAdapter(pub TopP);
+#[derive(Debug, Clone)]
+pub struct TopPFromStrAdapter(f64);
+
+impl TopPFromStrAdapter {
+    /// The number, which is known to be in bounds.
+    pub fn value(&self) -> f64 {
+        self.0
+    }
+}
 
 impl FromStr for TopPFromStrAdapter {
     type Err = anyhow::Error;
@@ -62,15 +164,41 @@ impl FromStr for TopPFromStrAdapter {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         s.parse::<f64>()
             .with_context(|| format!("the given string {} wasn't a valid float", s.bold()))?
-            .pipe(TopP::new)
+            .pipe(Self::try_from)
+    }
+}
+
+impl TryFrom<f64> for TopPFromStrAdapter {
+    type Error = anyhow::Error;
+
+    fn try_from(top_p: f64) -> Result<Self, Self::Error> {
+        TopP::new(top_p)
             .with_context(|| {
                 format!(
                     "the number {} wasn't in the required bound of 0.0..=1.0",
-                    s.bold()
+                    top_p.bold()
                 )
             })
-            .map(Self)
+            .map(|_| Self(top_p))
+    }
+}
+
+/// Convert the strings passed to `until` into a [`Stop`], if there are any.
+pub fn until_to_stop(until: &[String]) -> anyhow::Result<Option<Stop>> {
+    if until.is_empty() {
+        return Ok(None);
     }
+
+    until
+        .try_conv::<Stop>()
+        .with_context(|| {
+            format!(
+                "passed overflowing {} argument; expected <= 5 items but got {}",
+                "until".bold(),
+                until.len()
+            )
+        })
+        .map(Some)
 }
 
 #[derive(Debug)]
@@ -104,6 +232,22 @@ impl FromStr for EngineDefinitionFromStrAdapter {
     }
 }
 
+/// A template variable, passed as `key=value`.
+#[derive(Debug)]
+pub struct VariableFromStrAdapter(pub String, pub String);
+
+impl FromStr for VariableFromStrAdapter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, value) = s
+            .split_once('=')
+            .with_context(|| format!("expected {} but got {}", "key=value".bold(), s.bold()))?;
+
+        Ok(Self(key.to_string(), value.to_string()))
+    }
+}
+
 #[derive(Debug)]
 pub struct InfallibleFromStr<T: for<'a> From<&'a str>>(pub T);
 
@@ -119,6 +263,7 @@ impl<T: for<'a> From<&'a str>> FromStr for InfallibleFromStr<T> {
 pub enum Prompt {
     String(String),
     Stdin,
+    File(PathBuf),
 }
 
 impl Prompt {
@@ -127,13 +272,19 @@ impl Prompt {
             Self::String(string) => Ok(string),
             Self::Stdin => {
                 let mut stdin = io::stdin();
-                let mut buffer = String::new();
+                let mut buffer = Vec::new();
 
-                stdin.read_to_string(&mut buffer)
+                stdin.read_to_end(&mut buffer)
                     .context("failed to read standard input into buffer")?;
 
-                Ok(buffer)
+                decode_prompt(buffer).context("standard input isn't a valid prompt")
             }
+            Self::File(path) => fs::read(&path)
+                .with_context(|| format!("failed to read prompt file {}", path.display().bold()))?
+                .pipe(decode_prompt)
+                .with_context(|| {
+                    format!("prompt file {} isn't a valid prompt", path.display().bold())
+                }),
         }
     }
 }
@@ -142,11 +293,202 @@ impl From<&str> for Prompt {
     fn from(prompt: &str) -> Self {
         match prompt {
             "-" => Self::Stdin,
+
+            // a doubled @ escapes a prompt which starts with an @
+            _ if prompt.starts_with("@@") => Self::String(prompt[1..].into()),
+            _ if prompt.len() > 1 && prompt.starts_with('@') => Self::File(prompt[1..].into()),
             _ => Self::String(prompt.into()),
         }
     }
 }
 
+/// Turn the bytes of a prompt into a string, which must be utf-8. A utf-8 byte order mark is
+/// skipped.
+fn decode_prompt(bytes: Vec<u8>) -> anyhow::Result<String> {
+    if bytes.starts_with(&[0xFF, 0xFE]) || bytes.starts_with(&[0xFE, 0xFF]) {
+        anyhow::bail!("it is encoded as utf-16; convert it to utf-8 first")
+    }
+
+    let bytes = match bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        Some(bytes) => bytes.to_vec(),
+        None => bytes,
+    };
+
+    let prompt = String::from_utf8(bytes).map_err(|error| {
+        let valid = error.utf8_error().valid_up_to();
+        let line = error.as_bytes()[..valid]
+            .iter()
+            .filter(|&&byte| byte == b'\n')
+            .count()
+            + 1;
+
+        anyhow::anyhow!(
+            "invalid utf-8 at byte {} (line {}); it may be in another encoding, such as latin-1",
+            valid.bold(),
+            line.bold()
+        )
+    })?;
+
+    if let Some(index) = prompt.find('\0') {
+        anyhow::bail!(
+            "found a nul byte at byte {}; it looks like a binary file",
+            index.bold()
+        )
+    }
+
+    Ok(prompt)
+}
+
+/// Everything which makes up the prompt of a text completion: the rendered template and the
+/// prompts passed as an argument or with --prompt-file, joined by the separator, between the
+/// prefix and the suffix.
+#[derive(Debug, Default)]
+pub struct PromptSources {
+    pub template: Option<Invocation>,
+    pub prompts: Vec<Prompt>,
+    pub separator: Option<String>,
+    pub prefix: Option<String>,
+    pub suffix: Option<String>,
+}
+
+impl PromptSources {
+    /// What goes between two prompts when no separator was given.
+    pub const DEFAULT_SEPARATOR: &'static str = "\n";
+
+    pub fn new(
+        template: Option<Invocation>,
+        prompt: Option<InfallibleFromStr<Prompt>>,
+        prompt_files: Vec<PathBuf>,
+        separator: Option<String>,
+        prefix: Option<String>,
+        suffix: Option<String>,
+    ) -> Self {
+        Self {
+            template,
+            prompts: prompt
+                .map(|prompt| prompt.0)
+                .into_iter()
+                .chain(prompt_files.into_iter().map(Prompt::File))
+                .collect(),
+            separator,
+            prefix,
+            suffix,
+        }
+    }
+
+    /// Put the prompt together, returning it along with the sampling defaults of the template.
+    pub fn into_prompt(self) -> anyhow::Result<(String, Defaults)> {
+        if self.template.is_none() && self.prompts.is_empty() {
+            anyhow::bail!(
+                "no prompt was given; pass one as an argument, as {}, with {} or with {}",
+                "@path".italic(),
+                "--prompt-file".italic(),
+                "--template".italic()
+            )
+        }
+
+        ensure_stdin_read_once(&self.prompts)?;
+
+        let (template, defaults) = match self.template {
+            Some(template) => template
+                .render()
+                .map(|(prompt, defaults)| (Some(prompt), defaults))?,
+            None => (None, Defaults::default()),
+        };
+        let prompts = template
+            .into_iter()
+            .map(Ok)
+            .chain(self.prompts.into_iter().map(Prompt::into_string))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let separator = self.separator.as_deref().unwrap_or(Self::DEFAULT_SEPARATOR);
+        let prompt = format!(
+            "{}{}{}",
+            self.prefix.unwrap_or_default(),
+            prompts.join(separator),
+            self.suffix.unwrap_or_default()
+        );
+
+        Ok((prompt, defaults))
+    }
+}
+
+/// Standard input can only be read once, so only one prompt may come from it.
+pub fn ensure_stdin_read_once(prompts: &[Prompt]) -> anyhow::Result<()> {
+    let from_stdin = prompts
+        .iter()
+        .filter(|prompt| matches!(prompt, Prompt::Stdin))
+        .count();
+
+    if from_stdin > 1 {
+        anyhow::bail!(
+            "{} was passed more than once, but standard input can only be read once",
+            "-".bold()
+        )
+    }
+
+    Ok(())
+}
+
+/// How the prompt is written before the text completion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Echo {
+    /// Write the prompt as is.
+    Prompt,
+
+    /// Leave the prompt out, writing only the generated text.
+    None,
+
+    /// Write the prompt dimmed, so it can be told apart from the generated text. Falls back to
+    /// writing the prompt as is when stdout isn't a terminal.
+    Highlight,
+}
+
+impl Echo {
+    /// Write the prompt to stdout, the way this mode wants it.
+    pub fn write(self, prompt: &str) {
+        match self {
+            Self::Prompt => print!("{}", prompt),
+            Self::None => {}
+            Self::Highlight if io::stdout().is_terminal() => print!("{}", prompt.dimmed()),
+            Self::Highlight => print!("{}", prompt),
+        }
+    }
+}
+
+impl FromStr for Echo {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "prompt" => Ok(Self::Prompt),
+            "none" => Ok(Self::None),
+            "highlight" => Ok(Self::Highlight),
+            _ => anyhow::bail!(
+                "unknown echo mode {}; expected one of prompt, none or highlight",
+                s.bold()
+            ),
+        }
+    }
+}
+
+/// How the completions are ranked when there are many of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RankBy {
+    /// The log probability of the completion following the prompt.
+    LogProbability,
+}
+
+impl FromStr for RankBy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "logprob" | "log-probability" => Ok(Self::LogProbability),
+            _ => anyhow::bail!("unknown ranking {}; expected logprob", s.bold()),
+        }
+    }
+}
+
 #[derive(Debug, Parser)]
 pub enum SynthTextAction {
     /// This action returns the logarithm of the probability that a continuation is generated
@@ -154,18 +496,56 @@ pub enum SynthTextAction {
     /// (such as yes/no) are possible. It can also be used to benchmark the models.
     #[clap(visible_aliases = &["lp", "l"])]
     LogProbabilities {
-        /// If empty string, the context is set to the End-Of-Text token.
-        context: String,
+        /// If empty string, the context is set to the End-Of-Text token. Pass - to read it from
+        /// standard input, or @path to read it from a file.
+        context: InfallibleFromStr<Prompt>,
+
+        /// Must be a non empty string. Like the context, it can be read from standard input or
+        /// a file.
+        continuation: InfallibleFromStr<Prompt>,
 
-        /// Must be a non empty string.
-        continuation: NonEmptyStringFromStrAdapter,
+        /// Use the specified engine definition for this run instead of the configured one.
+        #[clap(short, long)]
+        engine: Option<EngineDefinitionFromStrAdapter>,
     },
 
     /// Completes and synthesizes text.
     #[clap(visible_aliases = &["tc", "t"])]
     TextCompletion {
-        /// The input text to complete.
-        prompt: InfallibleFromStr<Prompt>,
+        /// The input text to complete. Pass - to read it from standard input, or @path to read it
+        /// from a file; start it with @@ for a prompt which starts with an @.
+        prompt: Option<InfallibleFromStr<Prompt>>,
+
+        /// Render the template with the specified name into the prompt. Its sampling parameters
+        /// are used when they weren't passed on the command line.
+        #[clap(short = 'T', long, conflicts_with = "prompt")]
+        template: Option<String>,
+
+        /// Give a template variable a value, as key=value. Can be passed more than once.
+        #[clap(long = "var")]
+        vars: Vec<VariableFromStrAdapter>,
+
+        /// Read template variables from the specified json file, an object of keys and values.
+        /// Variables passed with --var take precedence.
+        #[clap(long)]
+        vars_file: Option<PathBuf>,
+
+        /// Read a part of the prompt from the specified file. Can be passed more than once; the
+        /// parts come after the prompt argument, in order.
+        #[clap(long)]
+        prompt_file: Vec<PathBuf>,
+
+        /// What to put between the parts of the prompt. Defaults to a newline.
+        #[clap(long)]
+        separator: Option<String>,
+
+        /// Text to put before the prompt.
+        #[clap(long)]
+        prefix: Option<String>,
+
+        /// Text to put after the prompt.
+        #[clap(long)]
+        suffix: Option<String>,
 
         /// Maximum number of tokens to generate. A token represents typically 4 or 5 characters
         /// for latin scripts.
@@ -192,14 +572,65 @@ pub enum SynthTextAction {
         #[clap(short = 'p', long)]
         top_p: Option<TopPFromStrAdapter>,
 
+        /// Use the specified engine definition for this run instead of the configured one.
+        #[clap(short, long)]
+        engine: Option<EngineDefinitionFromStrAdapter>,
+
+        /// How to write the prompt before the generated text: prompt, none, or highlight, which
+        /// dims the prompt when stdout is a terminal.
+        #[clap(long, default_value = "prompt")]
+        echo: Echo,
+
+        /// Leave the prompt out of the output, the same as --echo=none.
+        #[clap(long, conflicts_with = "echo")]
+        no_echo: bool,
+
         /// How to run this text completion.
         #[clap(subcommand)]
         method: SynthTextTextCompletionMethod,
     },
 
-    /// Generate or find the current configuration.
+    /// Generate, show, edit or find the current configuration.
     #[clap(subcommand)]
     Config(SynthTextConfig),
+
+    /// List, show, add or remove prompt templates, which are kept in the data directory.
+    #[clap(subcommand)]
+    Template(SynthTextTemplate),
+
+    /// Run the text completions of every row of a jsonl or csv file, a few at a time, and write
+    /// their results as jsonl in the order of the rows.
+    ///
+    /// Every row has a prompt, and can have an id and its own max_tokens, temperature, top_k,
+    /// top_p and until, which take precedence over the flags.
+    #[clap(visible_alias = "b")]
+    Batch(SynthTextBatch),
+
+    /// Start an interactive session, which keeps the connection, the engine and the sampling
+    /// parameters between completions. Type :help in it for the list of commands.
+    Repl {
+        /// Use the specified engine definition for this session instead of the configured one.
+        #[clap(short, long)]
+        engine: Option<EngineDefinitionFromStrAdapter>,
+    },
+
+    /// Report the config paths, the effective settings and whether the textsynth api can be
+    /// reached, along with its latency. Pass --base-url to check another server.
+    Doctor,
+}
+
+impl SynthTextAction {
+    /// The engine definition passed with --engine, if this action takes one.
+    pub fn engine_override(&self) -> Option<&EngineDefinition> {
+        match self {
+            Self::LogProbabilities { engine, .. } | Self::TextCompletion { engine, .. } => {
+                engine.as_ref().map(|engine| &engine.0)
+            }
+            Self::Batch(batch) => batch.engine.as_ref().map(|engine| &engine.0),
+            Self::Repl { engine } => engine.as_ref().map(|engine| &engine.0),
+            Self::Config(_) | Self::Template(_) | Self::Doctor => None,
+        }
+    }
 }
 
 #[derive(Debug, Parser)]
@@ -211,12 +642,33 @@ pub enum SynthTextTextCompletionMethod {
         /// contain the string. The length of the array is at most 5.
         #[clap(short, long)]
         until: Vec<String>,
+
+        /// How many completions to generate at the same time.
+        #[clap(short = 'n', long, default_value = "1")]
+        samples: usize,
+
+        /// Sort the completions by a score, the best one first. The only score is logprob, the
+        /// log probability of the completion following the prompt.
+        #[clap(long)]
+        rank_by: Option<RankBy>,
     },
 
     /// The output is streamed so that it is possible to display the result before the complete
     /// output is generated.
     #[clap(visible_alias = "s")]
-    Stream,
+    Stream {
+        /// Stop the generation when the string(s) are encountered. The generated text does not
+        /// contain the string. The length of the array is at most 5.
+        #[clap(short, long)]
+        until: Vec<String>,
+
+        /// If the connection drops mid-stream, continue from the text received so far instead of
+        /// failing, generating only the tokens which are left (the text received so far is
+        /// tokenized by the api to count them). Needs --max-tokens, or max_tokens in the config,
+        /// since the api's own default can't be counted against.
+        #[clap(short, long)]
+        resume: bool,
+    },
 }
 
 #[derive(Debug, Parser)]
@@ -226,6 +678,94 @@ pub enum SynthTextConfig {
     #[clap(visible_aliases = &["fp", "f"])]
     FindPath,
 
+    /// Show the configuration file, with API keys redacted.
+    #[clap(visible_alias = "s")]
+    Show {
+        /// Instead of the configuration file, show every effective value after the built-in
+        /// defaults, the configuration file, the environment and the command line flags have been
+        /// applied, along with where each value came from.
+        #[clap(short, long)]
+        resolved: bool,
+    },
+
+    /// Check the configuration for errors, such as an invalid schema, unsafe permissions, a
+    /// missing api key or an invalid engine definition, without making any requests.
+    #[clap(visible_alias = "v")]
+    Validate {
+        /// Also make a single cheap request to check that the api key works.
+        #[clap(long)]
+        online: bool,
+    },
+
+    /// Get the value of a key in the configuration file.
+    ///
+    /// Nested keys are separated by dots, for example `profiles.work.engine_definition`. If
+    /// --profile was passed, the key is relative to that profile.
+    Get {
+        /// The dotted key to get.
+        key: String,
+    },
+
+    /// Set the value of a key in the configuration file, keeping the rest of the file as is.
+    ///
+    /// Nested keys are separated by dots, for example `defaults.top_k`. If --profile was passed,
+    /// the key is relative to that profile. Values are parsed as json, and fall back to a plain
+    /// string otherwise. Engine definitions are parsed the same way as the --engine flag.
+    Set {
+        /// The dotted key to set.
+        key: String,
+
+        /// The new value of the key.
+        value: String,
+    },
+
+    /// Remove a key from the configuration file, keeping the rest of the file as is.
+    ///
+    /// Nested keys are separated by dots. If --profile was passed, the key is relative to that
+    /// profile.
+    Unset {
+        /// The dotted key to remove.
+        key: String,
+    },
+
+    /// Convert the configuration file to another format.
+    ///
+    /// The converted file is written next to the original, with the extension of the new format.
+    /// The original is removed afterwards, unless -k/--keep was passed.
+    #[clap(visible_alias = "cv")]
+    Convert {
+        /// The path of the configuration file.
+        path: Option<PathBuf>,
+
+        /// The format to convert to; one of json, toml or yaml.
+        #[clap(short, long)]
+        to: Format,
+
+        /// Keep the original configuration file.
+        #[clap(short, long)]
+        keep: bool,
+    },
+
+    /// Upgrade the configuration file to the latest format version.
+    ///
+    /// The original file is backed up next to it first, with the old version in its name.
+    #[clap(visible_alias = "m")]
+    Migrate {
+        /// The path of the configuration file.
+        path: Option<PathBuf>,
+    },
+
+    /// Interactively generate a configuration file, or update an existing one.
+    ///
+    /// Unlike `generate`, the API key is asked for without echoing it, so it doesn't end up in the
+    /// shell history. If the file already exists, the changes are shown before it is overwritten.
+    /// If --profile was passed, only that profile is configured.
+    #[clap(visible_alias = "i")]
+    Init {
+        /// The path of the configuration file.
+        path: Option<PathBuf>,
+    },
+
     /// Generate and write a configuration file.
     ///
     /// If no path was provided, it will be set to the default (or overridden by -c/--config) path
@@ -233,6 +773,9 @@ pub enum SynthTextConfig {
     ///
     /// As a precaution, if the file exists at the specified path, it will not continue. If this
     /// behavior is undesirable, pass the -c/--create argument.
+    ///
+    /// If --profile was passed, only that profile is added or updated; the rest of the existing
+    /// configuration file is kept as is.
     #[clap(visible_alias = "g")]
     Generate {
         /// The path of the configuration file.
@@ -246,16 +789,120 @@ pub enum SynthTextConfig {
         #[clap(short, long)]
         engine_definition: Option<EngineDefinitionFromStrAdapter>,
 
-        /// Do not write the configuration to a file. Instead, print it to stdout.
+        /// Do not write the configuration to a file. Instead, print it to stdout. With --profile,
+        /// only that profile is printed.
         #[clap(short, long)]
         dump: bool,
 
         /// Force creation of the configuration file to the specified location even if it exists.
         #[clap(short, long)]
         create: bool,
+
+        /// Make the profile passed with --profile the default profile.
+        #[clap(long = "default")]
+        make_default: bool,
+
+        /// The format to write the configuration file in; one of json, toml or yaml. Defaults to
+        /// the format picked from the extension of the path.
+        #[clap(short, long)]
+        format: Option<Format>,
+
+        /// Encrypt the configuration file with a passphrase. The passphrase is asked for, or read
+        /// from the SYNTHTEXT_PASSPHRASE environment variable.
+        ///
+        /// Encrypted configuration files are detected when they're loaded, and the passphrase is
+        /// asked for (or read from SYNTHTEXT_PASSPHRASE) again.
+        #[clap(long)]
+        encrypt: bool,
     },
 }
 
 pub fn parse() -> SynthText {
     SynthText::parse()
 }
+
+#[derive(Debug, Parser)]
+pub struct SynthTextBatch {
+    /// The file to read the rows from, or - for standard input.
+    pub input: PathBuf,
+
+    /// The format of the rows; jsonl or csv. Defaults to the format picked from the extension of
+    /// the input, or jsonl.
+    #[clap(short, long)]
+    pub format: Option<InputFormat>,
+
+    /// Write the results to the specified file instead of stdout.
+    #[clap(long)]
+    pub output_file: Option<PathBuf>,
+
+    /// How many text completions to run at the same time.
+    #[clap(short = 'j', long, default_value = "4")]
+    pub concurrency: usize,
+
+    /// Skip the rows which already have a result in the output file, matched by id or else by
+    /// position, and write the file again in the order of the rows. Rows which failed are run
+    /// again.
+    #[clap(long)]
+    pub resume: bool,
+
+    /// Maximum number of tokens to generate for the rows which don't set it.
+    #[clap(short, long)]
+    pub max_tokens: Option<usize>,
+
+    /// Sampling temperature for the rows which don't set it.
+    #[clap(short, long)]
+    pub temperature: Option<f64>,
+
+    /// Top k for the rows which don't set it.
+    #[clap(short = 'k', long)]
+    pub top_k: Option<TopKFromStrAdapter>,
+
+    /// Top p for the rows which don't set it.
+    #[clap(short = 'p', long)]
+    pub top_p: Option<TopPFromStrAdapter>,
+
+    /// Stop strings for the rows which don't set them; at most 5.
+    #[clap(short, long)]
+    pub until: Vec<String>,
+
+    /// Use the specified engine definition for this run instead of the configured one.
+    #[clap(short, long)]
+    pub engine: Option<EngineDefinitionFromStrAdapter>,
+}
+
+#[derive(Debug, Parser)]
+#[clap(visible_alias = "tpl")]
+pub enum SynthTextTemplate {
+    /// List the templates, along with their descriptions.
+    #[clap(visible_alias = "ls")]
+    List,
+
+    /// Show the source of a template.
+    #[clap(visible_alias = "s")]
+    Show {
+        /// The name of the template.
+        name: String,
+    },
+
+    /// Add a template, checking its syntax first.
+    #[clap(visible_alias = "a")]
+    Add {
+        /// The name of the template; letters, digits, - and _.
+        name: String,
+
+        /// The source of the template. Pass - to read it from standard input, or @path to read it
+        /// from a file.
+        source: InfallibleFromStr<Prompt>,
+
+        /// Overwrite the template if it already exists.
+        #[clap(short, long)]
+        force: bool,
+    },
+
+    /// Remove a template.
+    #[clap(visible_alias = "remove")]
+    Rm {
+        /// The name of the template.
+        name: String,
+    },
+}
@@ -0,0 +1,201 @@
+//! Passphrase based encryption of the configuration file.
+//!
+//! An encrypted configuration file is a json document with a single `encrypted` key, holding the
+//! parameters of the key derivation function, the nonce and the ciphertext. The key is derived
+//! from the passphrase with Argon2id and the configuration is encrypted with XChaCha20-Poly1305.
+
+use crate::verbosity::info;
+use anyhow::Context;
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use once_cell::sync::OnceCell;
+use owo_colors::OwoColorize;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::{env, fs};
+
+pub const PASSPHRASE_VAR: &str = "SYNTHTEXT_PASSPHRASE";
+const KDF: &str = "argon2id";
+const CIPHER: &str = "xchacha20poly1305";
+const KEY_LENGTH: usize = 32;
+const SALT_LENGTH: usize = 16;
+
+/// The passphrase which was last entered, so that it doesn't have to be entered again when an
+/// encrypted file is read and then written back.
+static PASSPHRASE: OnceCell<String> = OnceCell::new();
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Kdf {
+    algorithm: String,
+    memory_cost: u32,
+    time_cost: u32,
+    parallelism: u32,
+    salt: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Encrypted {
+    kdf: Kdf,
+    cipher: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Envelope {
+    encrypted: Encrypted,
+}
+
+/// Whether the contents of a configuration file are encrypted.
+pub fn is_encrypted(contents: &str) -> bool {
+    serde_json::from_str::<Envelope>(contents).is_ok()
+}
+
+/// Whether the configuration file at the location exists and is encrypted.
+pub fn is_encrypted_file(location: &Path) -> bool {
+    fs::read_to_string(location)
+        .map(|contents| is_encrypted(&contents))
+        .unwrap_or(false)
+}
+
+/// Get the passphrase from the `SYNTHTEXT_PASSPHRASE` environment variable, or by asking for it.
+/// If `confirm` is true, the passphrase has to be entered twice.
+pub fn passphrase(confirm: bool) -> anyhow::Result<&'static str> {
+    PASSPHRASE
+        .get_or_try_init(|| {
+            if let Ok(passphrase) = env::var(PASSPHRASE_VAR) {
+                if !passphrase.is_empty() {
+                    return Ok(passphrase);
+                }
+            }
+
+            let passphrase = rpassword::prompt_password(format!("{} ", "passphrase:".bold()))
+                .context("failed to read the passphrase")?;
+
+            if passphrase.is_empty() {
+                anyhow::bail!("the passphrase can't be empty")
+            }
+
+            if confirm {
+                let confirmation =
+                    rpassword::prompt_password(format!("{} ", "confirm passphrase:".bold()))
+                        .context("failed to read the passphrase")?;
+
+                if confirmation != passphrase {
+                    anyhow::bail!("the passphrases didn't match")
+                }
+            }
+
+            Ok(passphrase)
+        })
+        .map(String::as_str)
+}
+
+fn derive_key(passphrase: &str, kdf: &Kdf) -> anyhow::Result<[u8; KEY_LENGTH]> {
+    if kdf.algorithm != KDF {
+        anyhow::bail!(
+            "unsupported key derivation function {}",
+            kdf.algorithm.bold()
+        )
+    }
+
+    let salt = BASE64
+        .decode(&kdf.salt)
+        .context("failed to decode the salt")?;
+    let params = Params::new(
+        kdf.memory_cost,
+        kdf.time_cost,
+        kdf.parallelism,
+        Some(KEY_LENGTH),
+    )
+    .map_err(|error| anyhow::anyhow!("invalid key derivation parameters: {error}"))?;
+    let mut key = [0; KEY_LENGTH];
+
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+        .map_err(|error| anyhow::anyhow!("failed to derive the key: {error}"))?;
+
+    Ok(key)
+}
+
+/// Encrypt the plaintext with the passphrase, returning the contents of the encrypted file.
+pub fn encrypt(plaintext: &str, passphrase: &str) -> anyhow::Result<String> {
+    let mut salt = [0; SALT_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+
+    let kdf = Kdf {
+        algorithm: KDF.to_string(),
+        memory_cost: Params::DEFAULT_M_COST,
+        time_cost: Params::DEFAULT_T_COST,
+        parallelism: Params::DEFAULT_P_COST,
+        salt: BASE64.encode(salt),
+    };
+    let key = derive_key(passphrase, &kdf)?;
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = XChaCha20Poly1305::new(&key.into())
+        .encrypt(&nonce, plaintext.as_bytes())
+        .map_err(|_| anyhow::anyhow!("failed to encrypt the config"))?;
+    let envelope = Envelope {
+        encrypted: Encrypted {
+            kdf,
+            cipher: CIPHER.to_string(),
+            nonce: BASE64.encode(nonce),
+            ciphertext: BASE64.encode(ciphertext),
+        },
+    };
+
+    serde_json::to_string_pretty(&envelope).context("failed to serialize the encrypted config")
+}
+
+/// Decrypt the contents of an encrypted file with the passphrase.
+pub fn decrypt(contents: &str, passphrase: &str) -> anyhow::Result<String> {
+    let Envelope { encrypted } =
+        serde_json::from_str(contents).context("failed to parse the encrypted config")?;
+
+    if encrypted.cipher != CIPHER {
+        anyhow::bail!("unsupported cipher {}", encrypted.cipher.bold())
+    }
+
+    let key = derive_key(passphrase, &encrypted.kdf)?;
+    let nonce = BASE64
+        .decode(&encrypted.nonce)
+        .context("failed to decode the nonce")?;
+
+    if nonce.len() != 24 {
+        anyhow::bail!("the nonce has an invalid length")
+    }
+
+    let ciphertext = BASE64
+        .decode(&encrypted.ciphertext)
+        .context("failed to decode the ciphertext")?;
+    let plaintext = XChaCha20Poly1305::new(&key.into())
+        .decrypt(XNonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| {
+            anyhow::anyhow!("failed to decrypt the config; the passphrase is likely wrong")
+        })?;
+
+    String::from_utf8(plaintext).context("the decrypted config wasn't valid utf-8")
+}
+
+/// Decrypt the contents if they're encrypted, asking for the passphrase if needed. Otherwise, the
+/// contents are returned as is.
+pub fn decrypt_if_encrypted(contents: String, location: &Path) -> anyhow::Result<String> {
+    if !is_encrypted(&contents) {
+        return Ok(contents);
+    }
+
+    info!(
+        "the config at {} is encrypted",
+        location.display().bold()
+    );
+
+    decrypt(&contents, passphrase(false)?)
+        .with_context(|| format!("failed to decrypt the config at {}", location.display().bold()))
+}
@@ -0,0 +1,183 @@
+//! Editing of the raw configuration file through dotted keys, such as
+//! `profiles.work.engine_definition`.
+//!
+//! The file is edited as a plain json document (whatever its format is) rather than a [`Config`],
+//! so that fields which aren't known to this version of the program are kept as they are.
+
+use super::format::{self, Format};
+use super::{crypto, migrate, permissions, Config};
+use crate::EngineDefinitionFromStrAdapter;
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use serde_json::{Map, Value};
+use std::path::Path;
+use std::{fs, io};
+use tap::Pipe;
+use textsynth::prelude::EngineDefinition;
+
+fn segments(key: &str) -> anyhow::Result<Vec<&str>> {
+    let segments = key.split('.').collect::<Vec<_>>();
+
+    if segments.iter().any(|segment| segment.is_empty()) {
+        anyhow::bail!("the key {} contains an empty segment", key.bold())
+    }
+
+    Ok(segments)
+}
+
+/// Keys are split on dots, so a profile whose name contains one couldn't be edited.
+pub fn check_profile_name(name: &str) -> anyhow::Result<&str> {
+    if name.is_empty() || name.contains('.') {
+        anyhow::bail!(
+            "invalid profile name {}; it can't be empty or contain a {}",
+            name.bold(),
+            ".".bold()
+        )
+    }
+
+    Ok(name)
+}
+
+/// Load the configuration file at the location as a json document, decrypting it if needed. If
+/// `missing_ok` is true, a missing file is treated as an empty document of the current version.
+pub fn load(location: &Path, missing_ok: bool) -> anyhow::Result<Value> {
+    match fs::read_to_string(location) {
+        Ok(contents) => {
+            let document = permissions::check(location)
+                .and_then(|_| crypto::decrypt_if_encrypted(contents, location))?
+                .pipe_ref(|contents| Format::from_path(location).parse(contents))
+                .with_context(|| {
+                    format!(
+                        "failed to parse contents of path {}",
+                        location.display().bold()
+                    )
+                })?;
+            migrate::check_supported(&document)?;
+
+            Ok(document)
+        }
+        Err(error) if missing_ok && error.kind() == io::ErrorKind::NotFound => {
+            Ok(serde_json::json!({ "version": migrate::CURRENT_VERSION }))
+        }
+        Err(error) => {
+            Err(error).with_context(|| format!("failed to read path {}", location.display().bold()))
+        }
+    }
+}
+
+/// Write the document back to the location, in the format picked from its extension. If the file
+/// at the location is encrypted, the document is encrypted again with the same passphrase.
+pub fn save(location: &Path, document: &Value) -> anyhow::Result<()> {
+    save_as(location, document, crypto::is_encrypted_file(location))
+}
+
+/// Write the document to the location, in the format picked from its extension.
+pub fn save_as(location: &Path, document: &Value, encrypt: bool) -> anyhow::Result<()> {
+    let mut contents = Format::from_path(location)
+        .serialize(document)
+        .context("failed to serialize config")?;
+
+    if encrypt {
+        contents = crypto::encrypt(&contents, crypto::passphrase(false)?)?;
+    }
+
+    super::write_atomically(location, contents.as_bytes())
+}
+
+/// Check that the document is still a valid configuration.
+pub fn validate(document: &Value) -> anyhow::Result<()> {
+    serde_path_to_error::deserialize::<_, Config>(document.clone())
+        .map_err(format::describe)
+        .context("the configuration would no longer be valid")?
+        .validate()
+}
+
+pub fn get<'a>(document: &'a Value, key: &str) -> anyhow::Result<Option<&'a Value>> {
+    Ok(segments(key)?
+        .into_iter()
+        .try_fold(document, |value, segment| value.get(segment)))
+}
+
+pub fn set(document: &mut Value, key: &str, new: Value) -> anyhow::Result<()> {
+    let mut segments = segments(key)?;
+    let last = segments.pop().expect("split always yields at least one segment");
+    let mut current = document;
+
+    for segment in segments {
+        current = current
+            .as_object_mut()
+            .with_context(|| format!("a parent of the key {} is not an object", key.bold()))?
+            .entry(segment)
+            .or_insert_with(|| Value::Object(Map::new()));
+    }
+
+    current
+        .as_object_mut()
+        .with_context(|| format!("the parent of the key {} is not an object", key.bold()))?
+        .insert(last.to_string(), new);
+
+    Ok(())
+}
+
+/// Remove the key from the document, returning whether it existed.
+pub fn unset(document: &mut Value, key: &str) -> anyhow::Result<bool> {
+    let mut segments = segments(key)?;
+    let last = segments.pop().expect("split always yields at least one segment");
+    let parent = segments
+        .into_iter()
+        .try_fold(document, |value, segment| value.get_mut(segment));
+
+    Ok(parent
+        .and_then(Value::as_object_mut)
+        .map(|object| object.remove(last).is_some())
+        .unwrap_or(false))
+}
+
+/// A copy of the document with every api key redacted, for display.
+pub fn redacted(document: &Value) -> Value {
+    match document {
+        Value::Object(object) => object
+            .iter()
+            .map(|(key, value)| {
+                let value = match (key.as_str(), value) {
+                    ("api_key", Value::String(api_key)) => Value::String(super::redact(api_key)),
+                    (_, value) => redacted(value),
+                };
+
+                (key.clone(), value)
+            })
+            .collect::<Map<_, _>>()
+            .pipe(Value::Object),
+        Value::Array(array) => array.iter().map(redacted).collect(),
+        value => value.clone(),
+    }
+}
+
+/// Parse the value passed on the command line for the key. Engine definitions are parsed the same
+/// way as the --engine flag, and strings don't need to be quoted.
+pub fn parse_value(key: &str, value: &str) -> anyhow::Result<Value> {
+    let name = key.rsplit('.').next().unwrap_or(key);
+
+    match name {
+        "engine_definition" => {
+            let engine_definition = match value.parse::<EngineDefinitionFromStrAdapter>() {
+                Ok(EngineDefinitionFromStrAdapter(engine_definition)) => engine_definition,
+                Err(error) => {
+                    // also accept the name the engine definition is written as in the file
+                    serde_json::from_value::<EngineDefinition>(Value::String(value.to_string()))
+                        .map_err(|_| error)
+                        .with_context(|| {
+                            format!("invalid engine definition {}", value.bold())
+                        })?
+                }
+            };
+
+            serde_json::to_value(engine_definition).context("failed to serialize engine definition")
+        }
+        // keys which hold strings are never parsed as json, so that values such as `123` or `null`
+        // are kept as they're written
+        "api_key" | "api_key_command" | "api_key_file" | "default_profile" | "base_url"
+        | "proxy" | "ca_bundle" => Ok(Value::String(value.to_string())),
+        _ => Ok(serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()))),
+    }
+}
@@ -0,0 +1,103 @@
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The file formats a config file can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl Format {
+    pub const ALL: [Self; 3] = [Self::Json, Self::Toml, Self::Yaml];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Toml => "toml",
+            Self::Yaml => "yaml",
+        }
+    }
+
+    /// Pick the format from the extension of the path, defaulting to json.
+    pub fn from_path(path: &Path) -> Self {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(|extension| extension.parse().ok())
+            .unwrap_or(Self::Json)
+    }
+
+    /// Serialize the value through a json document, so that it can be parsed back with
+    /// [`Format::parse`]; yaml would otherwise write enums such as engine definitions as tags.
+    pub fn serialize(self, value: &impl Serialize) -> anyhow::Result<String> {
+        let value = serde_json::to_value(value)
+            .with_context(|| format!("failed to serialize to {}", self))?;
+        let result = match self {
+            Self::Json => serde_json::to_string_pretty(&value).map_err(anyhow::Error::from),
+            Self::Toml => toml::to_string_pretty(&value).map_err(anyhow::Error::from),
+            Self::Yaml => serde_yaml::to_string(&value).map_err(anyhow::Error::from),
+        };
+
+        result.with_context(|| format!("failed to serialize to {}", self))
+    }
+
+    /// Parse the contents into a json document, regardless of the format.
+    pub fn parse(self, contents: &str) -> anyhow::Result<serde_json::Value> {
+        let result = match self {
+            Self::Json => serde_json::from_str(contents).map_err(anyhow::Error::from),
+            Self::Toml => toml::from_str(contents).map_err(anyhow::Error::from),
+            Self::Yaml => serde_yaml::from_str(contents).map_err(anyhow::Error::from),
+        };
+
+        result.with_context(|| format!("failed to parse contents as {}", self))
+    }
+
+    /// Deserialize the contents, pointing to the field which failed to deserialize in errors.
+    pub fn deserialize<T: DeserializeOwned>(self, contents: &str) -> anyhow::Result<T> {
+        match self {
+            Self::Json => serde_path_to_error::deserialize(
+                &mut serde_json::Deserializer::from_str(contents),
+            )
+            .map_err(describe),
+            Self::Toml => {
+                serde_path_to_error::deserialize(toml::Deserializer::new(contents)).map_err(describe)
+            }
+            // serde_yaml only reads enums written as tags, so yaml goes through a json document
+            // like the one written by `serialize`
+            Self::Yaml => serde_path_to_error::deserialize(self.parse(contents)?).map_err(describe),
+        }
+    }
+}
+
+/// Describe a deserialization error along with the field it happened at.
+pub fn describe<E: fmt::Display>(error: serde_path_to_error::Error<E>) -> anyhow::Error {
+    anyhow::anyhow!("invalid field {}: {}", error.path().bold(), error.inner())
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "toml" => Ok(Self::Toml),
+            "yaml" | "yml" => Ok(Self::Yaml),
+            _ => anyhow::bail!(
+                "unknown format {}; expected one of json, toml or yaml",
+                s.bold()
+            ),
+        }
+    }
+}
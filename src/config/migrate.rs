@@ -0,0 +1,84 @@
+//! Versioning of the configuration format.
+//!
+//! Every change to the format gets a new version, along with a migration which upgrades a
+//! document of the previous version. Old documents are upgraded step by step until they reach the
+//! current version. Documents without a version are version 1, the format from before versioning.
+
+use crate::verbosity::tip;
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use serde_json::Value;
+
+pub const CURRENT_VERSION: u32 = 2;
+
+/// Upgrades a document from the version at the same index (plus one) to the next version.
+const MIGRATIONS: &[fn(&mut Value) -> anyhow::Result<()>] = &[v1_to_v2];
+
+pub const fn current_version() -> u32 {
+    CURRENT_VERSION
+}
+
+/// Version 2 added profiles, sampling defaults and api key commands and files. All of them are
+/// optional, so only the version has to be added.
+fn v1_to_v2(_document: &mut Value) -> anyhow::Result<()> {
+    Ok(())
+}
+
+pub fn version(document: &Value) -> anyhow::Result<u32> {
+    match document.get("version") {
+        None => Ok(1),
+        Some(version) => version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .filter(|version| *version >= 1)
+            .with_context(|| format!("the config version {} is not valid", version.bold())),
+    }
+}
+
+/// Fail if the document is of a version newer than this program supports.
+pub fn check_supported(document: &Value) -> anyhow::Result<u32> {
+    let version = version(document)?;
+
+    if version > CURRENT_VERSION {
+        tip!("upgrade synthtext to use this config");
+        anyhow::bail!(
+            "the config version {} is newer than the latest supported version {}",
+            version.bold(),
+            CURRENT_VERSION.bold()
+        )
+    }
+
+    Ok(version)
+}
+
+/// Upgrade the document to the current version, returning the version it was upgraded from if it
+/// had to be upgraded.
+pub fn migrate(document: &mut Value) -> anyhow::Result<Option<u32>> {
+    let version = check_supported(document)?;
+
+    if version == CURRENT_VERSION {
+        return Ok(None);
+    }
+
+    if !document.is_object() {
+        anyhow::bail!("the config is not a json object")
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize - 1) {
+        let from = index as u32 + 1;
+
+        migration(document).with_context(|| {
+            format!(
+                "failed to migrate the config from version {} to {}",
+                from.bold(),
+                (from + 1).bold()
+            )
+        })?;
+
+        if let Value::Object(object) = document {
+            object.insert("version".to_string(), Value::from(from + 1));
+        }
+    }
+
+    Ok(Some(version))
+}
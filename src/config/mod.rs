@@ -1,39 +1,361 @@
+pub mod crypto;
+pub mod edit;
+pub mod format;
+pub mod migrate;
 pub mod paths;
+pub mod permissions;
+pub mod resolve;
+pub mod secret;
 
 use anyhow::Context;
+use crate::{TopKFromStrAdapter, TopPFromStrAdapter};
+use crate::verbosity::{tip, verbose};
 use once_cell::sync::OnceCell;
 use owo_colors::OwoColorize;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use std::{fs, io};
-use tap::Pipe;
-use textsynth::prelude::EngineDefinition;
+use tap::{Pipe, Tap};
+use resolve::{Overrides, Resolved};
+use format::Format;
+use secret::ApiKey;
+use textsynth::prelude::{EngineDefinition, Stop};
 
-static CONFIG: OnceCell<Config> = OnceCell::new();
+static LAYERS: OnceCell<Vec<Layer>> = OnceCell::new();
+static RESOLVED: OnceCell<Resolved> = OnceCell::new();
 
-const fn default_engine_definition() -> EngineDefinition {
-    Config::DEFAULT_ENGINE_DEFINITION
+/// Sampling parameters which are used when they weren't passed on the command line.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Defaults {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<usize>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u16>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+
+    /// Stop strings for the text completion; at most 5.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub until: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct Config {
-    pub api_key: String,
+impl Defaults {
+    pub fn is_empty(&self) -> bool {
+        self.max_tokens.is_none()
+            && self.temperature.is_none()
+            && self.top_k.is_none()
+            && self.top_p.is_none()
+            && self.until.is_empty()
+    }
 
-    #[serde(default = "default_engine_definition")]
-    pub engine_definition: EngineDefinition,
+    /// Fall back to the other defaults for the values which aren't set.
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            max_tokens: self.max_tokens.or(other.max_tokens),
+            temperature: self.temperature.or(other.temperature),
+            top_k: self.top_k.or(other.top_k),
+            top_p: self.top_p.or(other.top_p),
+            until: if self.until.is_empty() {
+                other.until
+            } else {
+                self.until
+            },
+        }
+    }
+
+    pub fn top_k(&self) -> anyhow::Result<Option<TopKFromStrAdapter>> {
+        self.top_k
+            .map(TopKFromStrAdapter::try_from)
+            .transpose()
+            .with_context(|| format!("invalid default {}", "top_k".bold()))
+    }
+
+    pub fn top_p(&self) -> anyhow::Result<Option<TopPFromStrAdapter>> {
+        self.top_p
+            .map(TopPFromStrAdapter::try_from)
+            .transpose()
+            .with_context(|| format!("invalid default {}", "top_p".bold()))
+    }
+
+    pub fn until(&self) -> anyhow::Result<Option<Stop>> {
+        crate::until_to_stop(&self.until)
+            .with_context(|| format!("invalid default {}", "until".bold()))
+    }
+
+    /// Check the defaults with the same rules as their command line counterparts. The maximum
+    /// number of tokens can only be checked once the engine definition is known.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.top_k()?;
+        self.top_p()?;
+        self.until()?;
+
+        Ok(())
+    }
 }
 
-impl Config {
-    pub const DEFAULT_ENGINE_DEFINITION: EngineDefinition = EngineDefinition::GptJ6B;
+/// How to connect to the textsynth api.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Connection {
+    /// The base url of the api, for example a caching gateway or a local stand-in server.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+
+    /// An http(s) proxy to connect through.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+
+    /// How many seconds a request may take. For streams, how many seconds to wait for the api to
+    /// start answering, and then for each chunk.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<f64>,
+
+    /// How many seconds to wait for a connection to the api to be established.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout: Option<f64>,
+
+    /// A pem file of extra certificate authorities to trust. Relative paths are relative to the
+    /// directory of the config file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_bundle: Option<PathBuf>,
+}
+
+impl Connection {
+    pub fn is_empty(&self) -> bool {
+        self.base_url.is_none()
+            && self.proxy.is_none()
+            && self.timeout.is_none()
+            && self.connect_timeout.is_none()
+            && self.ca_bundle.is_none()
+    }
+
+    pub fn timeout(&self) -> anyhow::Result<Option<Duration>> {
+        seconds_to_duration(self.timeout)
+            .with_context(|| format!("invalid {}", "timeout".bold()))
+    }
+
+    pub fn connect_timeout(&self) -> anyhow::Result<Option<Duration>> {
+        seconds_to_duration(self.connect_timeout)
+            .with_context(|| format!("invalid {}", "connect_timeout".bold()))
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let Some(base_url) = &self.base_url {
+            reqwest::Url::parse(base_url)
+                .with_context(|| format!("invalid {} {}", "base_url".bold(), base_url.bold()))?;
+        }
+
+        if let Some(proxy) = &self.proxy {
+            reqwest::Proxy::all(proxy)
+                .with_context(|| format!("invalid {} {}", "proxy".bold(), proxy.bold()))?;
+        }
+
+        self.timeout()?;
+        self.connect_timeout()?;
+
+        Ok(())
+    }
+}
+
+/// When and how often to retry a failed request.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Retry {
+    /// How many times a request is made at most, including the first time. 1 disables retrying.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u32>,
+
+    /// How many seconds to wait before the first retry. The delay doubles after every attempt.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_delay: Option<f64>,
+
+    /// How many seconds to wait at most between two attempts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_delay: Option<f64>,
+
+    /// The fraction of the delay, from 0 to 1, which is randomized so that clients which failed
+    /// at the same time don't retry at the same time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jitter: Option<f64>,
+}
+
+impl Retry {
+    pub const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+    pub const DEFAULT_BASE_DELAY: Duration = Duration::from_secs(1);
+    pub const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(30);
+    pub const DEFAULT_JITTER: f64 = 0.5;
+
+    pub fn is_empty(&self) -> bool {
+        self.max_attempts.is_none()
+            && self.base_delay.is_none()
+            && self.max_delay.is_none()
+            && self.jitter.is_none()
+    }
+
+    pub fn max_attempts(&self) -> anyhow::Result<u32> {
+        match self.max_attempts {
+            Some(0) => anyhow::bail!("{} must be at least 1", "max_attempts".bold()),
+            Some(max_attempts) => Ok(max_attempts),
+            None => Ok(Self::DEFAULT_MAX_ATTEMPTS),
+        }
+    }
+
+    pub fn base_delay(&self) -> anyhow::Result<Duration> {
+        seconds_to_duration(self.base_delay)
+            .with_context(|| format!("invalid {}", "base_delay".bold()))
+            .map(|base_delay| base_delay.unwrap_or(Self::DEFAULT_BASE_DELAY))
+    }
+
+    pub fn max_delay(&self) -> anyhow::Result<Duration> {
+        seconds_to_duration(self.max_delay)
+            .with_context(|| format!("invalid {}", "max_delay".bold()))
+            .map(|max_delay| max_delay.unwrap_or(Self::DEFAULT_MAX_DELAY))
+    }
+
+    pub fn jitter(&self) -> anyhow::Result<f64> {
+        match self.jitter {
+            Some(jitter) if (0.0..=1.0).contains(&jitter) => Ok(jitter),
+            Some(jitter) => anyhow::bail!(
+                "{} must be between 0 and 1, not {}",
+                "jitter".bold(),
+                jitter.bold()
+            ),
+            None => Ok(Self::DEFAULT_JITTER),
+        }
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.max_attempts()?;
+        self.base_delay()?;
+        self.max_delay()?;
+        self.jitter()?;
+
+        Ok(())
+    }
+}
+
+fn seconds_to_duration(seconds: Option<f64>) -> anyhow::Result<Option<Duration>> {
+    match seconds {
+        Some(seconds) if seconds.is_finite() && seconds > 0.0 && seconds <= u32::MAX as f64 => {
+            Ok(Some(Duration::from_secs_f64(seconds)))
+        }
+        Some(seconds) => anyhow::bail!(
+            "{} is not a positive number of seconds",
+            seconds.bold()
+        ),
+        None => Ok(None),
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Profile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+
+    /// A shell command whose standard output is the api key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_command: Option<String>,
+
+    /// A file whose contents are the api key. Relative paths are relative to the directory of the
+    /// config file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_file: Option<PathBuf>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub engine_definition: Option<EngineDefinition>,
+
+    #[serde(default, skip_serializing_if = "Defaults::is_empty")]
+    pub defaults: Defaults,
+
+    #[serde(default, skip_serializing_if = "Connection::is_empty")]
+    pub connection: Connection,
+
+    #[serde(default, skip_serializing_if = "Retry::is_empty")]
+    pub retry: Retry,
+}
+
+impl Profile {
+    /// Where the api key of this profile is taken from. If more than one is specified, `api_key`
+    /// takes precedence over `api_key_command`, which takes precedence over `api_key_file`.
+    pub fn api_key(&self, directory: Option<&Path>) -> Option<ApiKey> {
+        if let Some(api_key) = &self.api_key {
+            return Some(ApiKey::Plain(api_key.clone()));
+        }
+
+        if let Some(command) = &self.api_key_command {
+            return Some(ApiKey::Command(command.clone()));
+        }
+
+        self.api_key_file.as_ref().map(|path| match directory {
+            Some(directory) => ApiKey::File(directory.join(path)),
+            None => ApiKey::File(path.clone()),
+        })
+    }
+}
+
+/// A config file which takes part in the resolution of the configuration.
+pub struct Layer {
+    pub location: PathBuf,
+    pub config: Config,
 
-    pub fn initialize() -> anyhow::Result<&'static Self> {
-        CONFIG.get_or_try_init(Self::load)
+    /// Whether this is a project-local config file, found by walking up from the current
+    /// directory, rather than the per-user config file.
+    pub project: bool,
+}
+
+impl Layer {
+    fn load(location: PathBuf, project: bool) -> anyhow::Result<Self> {
+        let config = if project {
+            Config::load_project(&location)?
+        } else {
+            Config::load_with_location(&location)?
+        };
+
+        Ok(Self {
+            location,
+            config,
+            project,
+        })
     }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Config {
+    /// The version of the config format; see the [`migrate`] module.
+    #[serde(default = "migrate::current_version")]
+    pub version: u32,
+
+    /// The profile to use when none was passed with --profile.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_profile: Option<String>,
+
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
+
+    /// The top level values, which every profile inherits from.
+    #[serde(flatten)]
+    pub base: Profile,
+}
 
-    pub fn initialize_with_location(location: &Path) -> anyhow::Result<&'static Self> {
-        CONFIG.get_or_try_init(|| Self::load_with_location(location))
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: migrate::CURRENT_VERSION,
+            default_profile: None,
+            profiles: BTreeMap::new(),
+            base: Profile::default(),
+        }
     }
+}
+
+impl Config {
+    pub const DEFAULT_ENGINE_DEFINITION: EngineDefinition = EngineDefinition::GptJ6B;
 
     pub fn load() -> anyhow::Result<Self> {
         Self::load_with_location(paths::location())
@@ -44,35 +366,134 @@ impl Config {
 
         if let Err(error) = &result {
             if let io::ErrorKind::NotFound = error.kind() {
-                alp::tip!("generate the configuration file first");
-                alp::tip!(
+                tip!("generate the configuration file first");
+                tip!(
                     "synthtext config generate --api-key {}",
                     "<API KEY>".italic()
                 );
+                tip!(
+                    "alternatively, don't pass a config file and set the {} environment variable",
+                    resolve::API_KEY_VAR.bold()
+                );
             }
         }
 
-        result
+        let contents =
+            result.with_context(|| format!("failed to read path {}", location.display().bold()))?;
+        permissions::check(location)?;
+
+        Self::from_contents(contents, location, false)
+    }
+
+    /// Load a project-local config file. Unlike the per-user config file, these are meant to be
+    /// committed to a repository, so their permissions aren't checked.
+    pub fn load_project(location: &Path) -> anyhow::Result<Self> {
+        fs::read_to_string(location)
             .with_context(|| format!("failed to read path {}", location.display().bold()))?
-            .pipe_ref(|contents| serde_json::from_str(contents))
-            .with_context(|| {
-                format!(
-                    "failed to parse contents of path {} to json",
-                    location.display().bold()
-                )
-            })
+            .pipe(|contents| Self::from_contents(contents, location, true))
     }
 
-    pub fn get() -> &'static Self {
-        CONFIG.get().expect("config not initialized")
+    fn from_contents(contents: String, location: &Path, project: bool) -> anyhow::Result<Self> {
+        let config = contents
+            .pipe(|contents| crypto::decrypt_if_encrypted(contents, location))?
+            .pipe_ref(|contents| Self::parse(contents, location, project))?;
+
+        config
+            .validate()
+            .with_context(|| format!("invalid config at path {}", location.display().bold()))?;
+
+        Ok(config)
+    }
+
+    /// Parse the contents of the config file at the location, upgrading them in memory if they're
+    /// of an older version. The format is picked from the extension of the location.
+    fn parse(contents: &str, location: &Path, project: bool) -> anyhow::Result<Self> {
+        let file_format = Format::from_path(location);
+        let mut document = file_format.parse(contents).with_context(|| {
+            format!(
+                "failed to parse contents of path {}",
+                location.display().bold()
+            )
+        })?;
+        let result = match migrate::migrate(&mut document)? {
+            // deserialize from the original contents, so that errors point to a line
+            None => file_format.deserialize(contents),
+            // project-local files are shared with others, who may run an older version of the
+            // program, so they aren't nagged about
+            Some(version) if project => {
+                verbose!(
+                    "the project config at {} is of an older format (version {})",
+                    location.display().bold(),
+                    version.bold()
+                );
+                serde_path_to_error::deserialize(document).map_err(format::describe)
+            }
+            Some(version) => {
+                tip!(
+                    "the config at {} is of an older format (version {}); upgrade it with {}",
+                    location.display().bold(),
+                    version.bold(),
+                    "synthtext config migrate".italic()
+                );
+                serde_path_to_error::deserialize(document).map_err(format::describe)
+            }
+        };
+
+        result.with_context(|| format!("invalid config at path {}", location.display().bold()))
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.base
+            .defaults
+            .validate()
+            .context("invalid defaults at the top level")?;
+        self.base
+            .connection
+            .validate()
+            .context("invalid connection at the top level")?;
+        self.base
+            .retry
+            .validate()
+            .context("invalid retry policy at the top level")?;
+
+        for (name, profile) in &self.profiles {
+            profile
+                .defaults
+                .validate()
+                .with_context(|| format!("invalid defaults in profile {}", name.bold()))?;
+            profile
+                .connection
+                .validate()
+                .with_context(|| format!("invalid connection in profile {}", name.bold()))?;
+            profile
+                .retry
+                .validate()
+                .with_context(|| format!("invalid retry policy in profile {}", name.bold()))?;
+        }
+
+        Ok(())
     }
 
-    pub fn write(&self, mut writer: impl Write) -> anyhow::Result<()> {
-        let contents = serde_json::to_string_pretty(self).context("failed to serialize config")?;
+    pub fn write(&self, mut writer: impl Write, format: Format) -> anyhow::Result<()> {
+        let contents = format.serialize(self).context("failed to serialize config")?;
         let contents = contents.as_bytes();
 
         writer.write_all(contents).context("failed to write config")
     }
+
+    pub fn write_encrypted(
+        &self,
+        mut writer: impl Write,
+        format: Format,
+        passphrase: &str,
+    ) -> anyhow::Result<()> {
+        let contents = format.serialize(self).context("failed to serialize config")?;
+        let contents = crypto::encrypt(&contents, passphrase)?;
+
+        writer
+            .write_all(contents.as_bytes())
+            .context("failed to write config")
+    }
 }
 
 #[allow(dead_code)]
@@ -81,22 +502,181 @@ pub fn load() -> anyhow::Result<Config> {
     Config::load()
 }
 
-#[allow(dead_code)]
 pub fn load_with_location(location: &Path) -> anyhow::Result<Config> {
     paths::initialize()?;
     Config::load_with_location(location)
 }
 
-pub fn initialize() -> anyhow::Result<&'static Config> {
+/// Load every config file which takes part in the resolution of the configuration, from the
+/// lowest to the highest precedence: the per-user config file (or the specified one), then the
+/// project-local config files from the outermost directory to the current one.
+///
+/// Unlike an explicitly specified location, it is not an error for the default location to not
+/// exist; the built-in defaults and the environment are used instead.
+pub fn load_layers(location: Option<&Path>) -> anyhow::Result<Vec<Layer>> {
     paths::initialize()?;
-    Config::initialize()
+
+    let mut layers = Vec::new();
+
+    match location {
+        Some(location) => layers.push(Layer::load(location.to_path_buf(), false)?),
+        None if paths::location().exists() => {
+            layers.push(Layer::load(paths::location().to_path_buf(), false)?)
+        }
+        None => {}
+    }
+
+    for location in paths::project_locations()? {
+        layers.push(Layer::load(location, true)?);
+    }
+
+    Ok(layers)
 }
 
-pub fn initialize_with_location(location: &Path) -> anyhow::Result<&'static Config> {
-    paths::initialize()?;
-    Config::initialize_with_location(location)
+pub fn initialize() -> anyhow::Result<&'static [Layer]> {
+    LAYERS.get_or_try_init(|| load_layers(None)).map(Vec::as_slice)
+}
+
+pub fn initialize_with_location(location: &Path) -> anyhow::Result<&'static [Layer]> {
+    LAYERS
+        .get_or_try_init(|| load_layers(Some(location)))
+        .map(Vec::as_slice)
+}
+
+pub fn layers() -> &'static [Layer] {
+    LAYERS.get().expect("config not initialized")
+}
+
+/// Resolve the effective configuration which will be used for the rest of the program. Requires
+/// the config to be initialized.
+pub fn resolve(overrides: Overrides) -> anyhow::Result<&'static Resolved> {
+    RESOLVED.get_or_try_init(|| resolve::resolve(layers(), overrides))
+}
+
+pub fn resolved() -> &'static Resolved {
+    RESOLVED.get().expect("config not resolved")
+}
+
+/// Write the contents to the location by writing them to a temporary file in the same directory
+/// first and renaming it over the location, so that the location is never left half written.
+pub fn write_atomically(location: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    let file_name = location
+        .file_name()
+        .with_context(|| format!("the path {} has no file name", location.display().bold()))?;
+    let temporary = location.with_file_name(format!(".{}.tmp", file_name.to_string_lossy()));
+
+    if let Some(parent) = location.parent() {
+        permissions::create_dir_all(parent).with_context(|| {
+            format!(
+                "failed to create parent directory {}",
+                parent.display().bold()
+            )
+        })?;
+    }
+
+    // a temporary file left behind by an earlier write is removed rather than reused, since the
+    // mode only applies to files which are created
+    match fs::remove_file(&temporary) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => {
+            return Err(error).with_context(|| {
+                format!("failed to remove path {}", temporary.display().bold())
+            })
+        }
+        _ => {}
+    }
+
+    let mut file = fs::OpenOptions::new()
+        .tap_mut(|this| {
+            this.write(true).create_new(true);
+        })
+        .tap_mut(permissions::restrict)
+        .open(&temporary)
+        .with_context(|| format!("failed to create path {}", temporary.display().bold()))?;
+
+    file.write_all(contents)
+        .and_then(|_| file.sync_all())
+        .with_context(|| format!("failed to write to path {}", temporary.display().bold()))?;
+    fs::rename(&temporary, location).with_context(|| {
+        format!(
+            "failed to move path {} to {}",
+            temporary.display().bold(),
+            location.display().bold()
+        )
+    })
+}
+
+/// Hide all but the last few characters of an API key.
+pub fn redact(api_key: &str) -> String {
+    const VISIBLE: usize = 4;
+    let length = api_key.chars().count();
+
+    if length <= VISIBLE * 2 {
+        "*".repeat(length)
+    } else {
+        let visible = api_key.chars().skip(length - VISIBLE).collect::<String>();
+        format!("{}{visible}", "*".repeat(length - VISIBLE))
+    }
+}
+
+/// Hide the password of a url, such as a proxy's.
+pub fn redact_url(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(mut url) if url.password().is_some() => {
+            let _ = url.set_password(Some("***"));
+            url.to_string()
+        }
+        _ => url.to_string(),
+    }
 }
 
-pub fn get() -> &'static Config {
-    Config::get()
+#[cfg(test)]
+mod tests {
+    use super::format::Format;
+    use super::{Config, Profile};
+    use crate::EngineDefinitionFromStrAdapter;
+    use std::path::Path;
+
+    /// A config the way `config generate` writes it, with a custom engine definition at the top
+    /// level and in a profile.
+    fn generated() -> Config {
+        let engine_definition = "my_engine,1024"
+            .parse::<EngineDefinitionFromStrAdapter>()
+            .unwrap()
+            .0;
+        let mut config = Config {
+            base: Profile {
+                api_key: Some("top level key".to_string()),
+                engine_definition: Some(engine_definition.clone()),
+                ..Profile::default()
+            },
+            ..Config::default()
+        };
+        let profile = config.profiles.entry("work".to_string()).or_default();
+        profile.api_key = Some("work key".to_string());
+        profile.engine_definition = Some(engine_definition);
+        config.default_profile = Some("work".to_string());
+
+        config
+    }
+
+    #[test]
+    fn generated_configs_can_be_parsed_back_in_every_format() {
+        for format in Format::ALL {
+            let config = generated();
+            let mut contents = Vec::new();
+            config.write(&mut contents, format).unwrap();
+            let contents = String::from_utf8(contents).unwrap();
+            let location = Path::new("config").with_extension(format.extension());
+
+            let parsed = Config::parse(&contents, &location, false)
+                .unwrap_or_else(|error| panic!("{}: {:#}\n{}", format, error, contents));
+
+            assert_eq!(
+                serde_json::to_value(&parsed).unwrap(),
+                serde_json::to_value(&config).unwrap(),
+                "{}",
+                format
+            );
+        }
+    }
 }
@@ -1,14 +1,22 @@
+use super::format::Format;
 use anyhow::Context;
 use directories::ProjectDirs;
 use once_cell::sync::{Lazy, OnceCell};
+use std::env;
 use std::path::{Path, PathBuf};
 
 const QUALIFIER: &str = "com";
 const ORGANIZATION: &str = "ALinuxPerson";
 const APPLICATION: &str = "synthtext";
+const PROJECT_FILE_STEM: &str = ".synthtext";
 static PROJECT_DIRS: OnceCell<ProjectDirs> = OnceCell::new();
 static DIRECTORY: Lazy<&Path> = Lazy::new(|| project_dirs().config_dir());
-static LOCATION: Lazy<PathBuf> = Lazy::new(|| directory().join("config.json"));
+static LOCATION: Lazy<PathBuf> = Lazy::new(|| {
+    candidates()
+        .into_iter()
+        .find(|candidate| candidate.exists())
+        .unwrap_or_else(|| location_with_format(Format::Json))
+});
 
 pub fn initialize() -> anyhow::Result<()> {
     if PROJECT_DIRS.get().is_none() {
@@ -33,3 +41,46 @@ pub fn directory() -> &'static Path {
 pub fn location() -> &'static Path {
     &LOCATION
 }
+
+/// The directory prompt templates are kept in.
+pub fn templates_directory() -> PathBuf {
+    project_dirs().data_dir().join("templates")
+}
+
+/// The file the history of the repl is kept in.
+pub fn history_location() -> PathBuf {
+    project_dirs().data_dir().join("repl_history")
+}
+
+/// The location of the config file if it were written in the specified format.
+pub fn location_with_format(format: Format) -> PathBuf {
+    directory().join(format!("config.{}", format.extension()))
+}
+
+/// Every location the config file is looked for at, in order. The first one which exists is used.
+pub fn candidates() -> Vec<PathBuf> {
+    Format::ALL
+        .into_iter()
+        .map(location_with_format)
+        .chain([directory().join("config.yml")])
+        .collect()
+}
+
+/// Find the project-local config files by walking up from the current directory, like
+/// `.editorconfig`. They are returned from the outermost directory to the current one, which is the
+/// order of their precedence.
+pub fn project_locations() -> anyhow::Result<Vec<PathBuf>> {
+    let current_directory = env::current_dir().context("failed to get the current directory")?;
+    let mut locations = current_directory
+        .ancestors()
+        .filter_map(|directory| {
+            Format::ALL
+                .into_iter()
+                .map(|format| directory.join(format!("{PROJECT_FILE_STEM}.{}", format.extension())))
+                .find(|candidate| candidate.is_file())
+        })
+        .collect::<Vec<_>>();
+    locations.reverse();
+
+    Ok(locations)
+}
@@ -0,0 +1,108 @@
+//! Keeping configuration files, which hold api keys, private to the current user.
+
+use crate::verbosity::{tip, warn};
+#[cfg(unix)]
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+const FILE_MODE: u32 = 0o600;
+
+#[cfg(unix)]
+const DIRECTORY_MODE: u32 = 0o700;
+
+/// Refuse to load config files which other users can read, instead of only warning about them.
+pub fn set_strict(strict: bool) {
+    STRICT.store(strict, Ordering::Relaxed)
+}
+
+/// Make files opened with the options only accessible to the current user when they're created.
+pub fn restrict(options: &mut fs::OpenOptions) {
+    #[cfg(unix)]
+    options.mode(FILE_MODE);
+
+    #[cfg(not(unix))]
+    let _ = options;
+}
+
+/// Recursively create the directory, making the directories which are created only accessible to
+/// the current user.
+pub fn create_dir_all(path: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+
+    #[cfg(unix)]
+    builder.mode(DIRECTORY_MODE);
+
+    builder.create(path)
+}
+
+/// Get the permissions of the config file at the location if other users can access it.
+#[cfg(unix)]
+pub fn accessible_by_others(location: &Path) -> anyhow::Result<Option<u32>> {
+    let mode = fs::metadata(location)
+        .with_context(|| format!("failed to get metadata of path {}", location.display().bold()))?
+        .permissions()
+        .mode();
+
+    if mode & 0o077 == 0 {
+        Ok(None)
+    } else {
+        Ok(Some(mode & 0o777))
+    }
+}
+
+#[cfg(not(unix))]
+pub fn accessible_by_others(_location: &Path) -> anyhow::Result<Option<u32>> {
+    Ok(None)
+}
+
+/// The command which restricts the config file at the location to the current user.
+#[cfg(unix)]
+pub fn fix_command(location: &Path) -> String {
+    format!("chmod {:o} {}", FILE_MODE, location.display())
+}
+
+#[cfg(not(unix))]
+pub fn fix_command(_location: &Path) -> String {
+    String::new()
+}
+
+/// Warn if the config file at the location can be read by other users, or fail if strict
+/// permissions were requested.
+pub fn check(location: &Path) -> anyhow::Result<()> {
+    let mode = match accessible_by_others(location)? {
+        Some(mode) => mode,
+        None => return Ok(()),
+    };
+    let message = format!(
+        "the config at {} can be accessed by other users (permissions {:o})",
+        location.display().bold(),
+        mode
+    );
+    let strict = STRICT.load(Ordering::Relaxed);
+
+    if !strict {
+        warn!("{message}");
+    }
+
+    tip!(
+        "restrict it to the current user with {}",
+        fix_command(location).italic()
+    );
+
+    if strict {
+        anyhow::bail!("{message}")
+    }
+
+    Ok(())
+}
@@ -0,0 +1,775 @@
+use super::secret::ApiKey;
+use super::{Config, Connection, Defaults, Layer, Profile, Retry};
+use crate::EngineDefinitionFromStrAdapter;
+use crate::verbosity::{tip, warn};
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::{env, fmt};
+use textsynth::prelude::EngineDefinition;
+
+pub const PROFILE_VAR: &str = "SYNTHTEXT_PROFILE";
+pub const API_KEY_VAR: &str = "SYNTHTEXT_API_KEY";
+pub const API_KEY_COMMAND_VAR: &str = "SYNTHTEXT_API_KEY_COMMAND";
+pub const API_KEY_FILE_VAR: &str = "SYNTHTEXT_API_KEY_FILE";
+pub const ENGINE_VAR: &str = "SYNTHTEXT_ENGINE";
+pub const MAX_TOKENS_VAR: &str = "SYNTHTEXT_MAX_TOKENS";
+pub const TEMPERATURE_VAR: &str = "SYNTHTEXT_TEMPERATURE";
+pub const TOP_K_VAR: &str = "SYNTHTEXT_TOP_K";
+pub const TOP_P_VAR: &str = "SYNTHTEXT_TOP_P";
+pub const BASE_URL_VAR: &str = "SYNTHTEXT_BASE_URL";
+pub const PROXY_VAR: &str = "SYNTHTEXT_PROXY";
+pub const TIMEOUT_VAR: &str = "SYNTHTEXT_TIMEOUT";
+pub const CONNECT_TIMEOUT_VAR: &str = "SYNTHTEXT_CONNECT_TIMEOUT";
+pub const CA_BUNDLE_VAR: &str = "SYNTHTEXT_CA_BUNDLE";
+pub const MAX_ATTEMPTS_VAR: &str = "SYNTHTEXT_MAX_ATTEMPTS";
+pub const RETRY_DELAY_VAR: &str = "SYNTHTEXT_RETRY_DELAY";
+pub const RETRY_MAX_DELAY_VAR: &str = "SYNTHTEXT_RETRY_MAX_DELAY";
+pub const RETRY_JITTER_VAR: &str = "SYNTHTEXT_RETRY_JITTER";
+
+/// Where a resolved value came from.
+#[derive(Clone)]
+pub enum Source {
+    Default,
+    File {
+        location: PathBuf,
+        profile: Option<String>,
+    },
+    Environment(&'static str),
+    Cli(&'static str),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Default => write!(f, "built-in default"),
+            Self::File {
+                location,
+                profile: None,
+            } => write!(f, "config file {}", location.display()),
+            Self::File {
+                location,
+                profile: Some(profile),
+            } => write!(
+                f,
+                "profile {} of config file {}",
+                profile,
+                location.display()
+            ),
+            Self::Environment(name) => write!(f, "environment variable {name}"),
+            Self::Cli(flag) => write!(f, "command line flag {flag}"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Sourced<T> {
+    pub value: T,
+    pub source: Source,
+}
+
+/// Values passed on the command line, which take precedence over every other layer.
+#[derive(Clone, Default)]
+pub struct Overrides {
+    pub profile: Option<String>,
+    pub engine_definition: Option<EngineDefinition>,
+    pub connection: Connection,
+    pub retry: Retry,
+}
+
+/// The effective configuration, resolved from the built-in defaults, the config file, the
+/// `SYNTHTEXT_*` environment variables and the command line, in that order.
+pub struct Resolved {
+    pub profile: Option<Sourced<String>>,
+    pub api_key: Option<Sourced<ApiKey>>,
+    pub engine_definition: Sourced<EngineDefinition>,
+    pub max_tokens: Option<Sourced<usize>>,
+    pub temperature: Option<Sourced<f64>>,
+    pub top_k: Option<Sourced<u16>>,
+    pub top_p: Option<Sourced<f64>>,
+    pub until: Option<Sourced<Vec<String>>>,
+    pub base_url: Option<Sourced<String>>,
+    pub proxy: Option<Sourced<String>>,
+    pub timeout: Option<Sourced<f64>>,
+    pub connect_timeout: Option<Sourced<f64>>,
+    pub ca_bundle: Option<Sourced<PathBuf>>,
+    pub max_attempts: Option<Sourced<u32>>,
+    pub base_delay: Option<Sourced<f64>>,
+    pub max_delay: Option<Sourced<f64>>,
+    pub jitter: Option<Sourced<f64>>,
+}
+
+impl Resolved {
+    fn new() -> Self {
+        Self {
+            profile: None,
+            api_key: None,
+            engine_definition: Sourced {
+                value: Config::DEFAULT_ENGINE_DEFINITION,
+                source: Source::Default,
+            },
+            max_tokens: None,
+            temperature: None,
+            top_k: None,
+            top_p: None,
+            until: None,
+            base_url: None,
+            proxy: None,
+            timeout: None,
+            connect_timeout: None,
+            ca_bundle: None,
+            max_attempts: None,
+            base_delay: None,
+            max_delay: None,
+            jitter: None,
+        }
+    }
+
+    fn layer_profile(&mut self, layer: &Layer, profile: &Profile, source: impl Fn() -> Source) {
+        let api_key = match profile.api_key(layer.location.parent()) {
+            // a project-local config could come from anywhere, so don't let it run commands or
+            // read files
+            Some(ApiKey::Command(_) | ApiKey::File(_)) if layer.project => {
+                warn!(
+                    "ignoring {} and {} in the project-local config at {}",
+                    "api_key_command".bold(),
+                    "api_key_file".bold(),
+                    layer.location.display().bold()
+                );
+                None
+            }
+            api_key => api_key,
+        };
+        override_with(&mut self.api_key, api_key, &source);
+
+        if let Some(engine_definition) = &profile.engine_definition {
+            self.engine_definition = Sourced {
+                value: engine_definition.clone(),
+                source: source(),
+            };
+        }
+
+        override_with(&mut self.max_tokens, profile.defaults.max_tokens, &source);
+        override_with(&mut self.temperature, profile.defaults.temperature, &source);
+        override_with(&mut self.top_k, profile.defaults.top_k, &source);
+        override_with(&mut self.top_p, profile.defaults.top_p, &source);
+
+        if !profile.defaults.until.is_empty() {
+            override_with(&mut self.until, Some(profile.defaults.until.clone()), &source);
+        }
+
+        let connection = &profile.connection;
+
+        // a project-local config could redirect the api key to another server, or intercept the
+        // connection
+        if layer.project
+            && (connection.base_url.is_some()
+                || connection.proxy.is_some()
+                || connection.ca_bundle.is_some())
+        {
+            warn!(
+                "ignoring {}, {} and {} in the project-local config at {}",
+                "base_url".bold(),
+                "proxy".bold(),
+                "ca_bundle".bold(),
+                layer.location.display().bold()
+            );
+        } else {
+            override_with(&mut self.base_url, connection.base_url.clone(), &source);
+            override_with(&mut self.proxy, connection.proxy.clone(), &source);
+
+            let ca_bundle = connection.ca_bundle.as_ref().map(|path| {
+                match layer.location.parent() {
+                    Some(directory) => directory.join(path),
+                    None => path.clone(),
+                }
+            });
+            override_with(&mut self.ca_bundle, ca_bundle, &source);
+        }
+
+        override_with(&mut self.timeout, connection.timeout, &source);
+        override_with(&mut self.connect_timeout, connection.connect_timeout, &source);
+
+        let retry = &profile.retry;
+        override_with(&mut self.max_attempts, retry.max_attempts, &source);
+        override_with(&mut self.base_delay, retry.base_delay, &source);
+        override_with(&mut self.max_delay, retry.max_delay, &source);
+        override_with(&mut self.jitter, retry.jitter, &source);
+    }
+
+    fn layer_cli_connection(&mut self, connection: Connection) {
+        override_with(&mut self.base_url, connection.base_url, || {
+            Source::Cli("--base-url")
+        });
+        override_with(&mut self.proxy, connection.proxy, || Source::Cli("--proxy"));
+        override_with(&mut self.timeout, connection.timeout, || {
+            Source::Cli("--timeout")
+        });
+        override_with(&mut self.connect_timeout, connection.connect_timeout, || {
+            Source::Cli("--connect-timeout")
+        });
+        override_with(&mut self.ca_bundle, connection.ca_bundle, || {
+            Source::Cli("--ca-bundle")
+        });
+    }
+
+    fn layer_cli_retry(&mut self, retry: Retry) {
+        override_with(&mut self.max_attempts, retry.max_attempts, || {
+            Source::Cli("--max-attempts")
+        });
+        override_with(&mut self.base_delay, retry.base_delay, || {
+            Source::Cli("--retry-delay")
+        });
+        override_with(&mut self.max_delay, retry.max_delay, || {
+            Source::Cli("--retry-max-delay")
+        });
+        override_with(&mut self.jitter, retry.jitter, || {
+            Source::Cli("--retry-jitter")
+        });
+    }
+
+    fn layer_environment(&mut self) -> anyhow::Result<()> {
+        override_with(
+            &mut self.api_key,
+            env_var(API_KEY_FILE_VAR)?.map(|path| ApiKey::File(path.into())),
+            || Source::Environment(API_KEY_FILE_VAR),
+        );
+        override_with(
+            &mut self.api_key,
+            env_var(API_KEY_COMMAND_VAR)?.map(ApiKey::Command),
+            || Source::Environment(API_KEY_COMMAND_VAR),
+        );
+        override_with(
+            &mut self.api_key,
+            env_var(API_KEY_VAR)?.map(ApiKey::Plain),
+            || Source::Environment(API_KEY_VAR),
+        );
+
+        if let Some(EngineDefinitionFromStrAdapter(engine_definition)) =
+            parsed_env_var(ENGINE_VAR)?
+        {
+            self.engine_definition = Sourced {
+                value: engine_definition,
+                source: Source::Environment(ENGINE_VAR),
+            };
+        }
+
+        override_with(&mut self.max_tokens, parsed_env_var(MAX_TOKENS_VAR)?, || {
+            Source::Environment(MAX_TOKENS_VAR)
+        });
+        override_with(&mut self.temperature, parsed_env_var(TEMPERATURE_VAR)?, || {
+            Source::Environment(TEMPERATURE_VAR)
+        });
+        override_with(&mut self.top_k, parsed_env_var(TOP_K_VAR)?, || {
+            Source::Environment(TOP_K_VAR)
+        });
+        override_with(&mut self.top_p, parsed_env_var(TOP_P_VAR)?, || {
+            Source::Environment(TOP_P_VAR)
+        });
+        override_with(&mut self.base_url, env_var(BASE_URL_VAR)?, || {
+            Source::Environment(BASE_URL_VAR)
+        });
+        override_with(&mut self.proxy, env_var(PROXY_VAR)?, || {
+            Source::Environment(PROXY_VAR)
+        });
+        override_with(&mut self.timeout, parsed_env_var(TIMEOUT_VAR)?, || {
+            Source::Environment(TIMEOUT_VAR)
+        });
+        override_with(
+            &mut self.connect_timeout,
+            parsed_env_var(CONNECT_TIMEOUT_VAR)?,
+            || Source::Environment(CONNECT_TIMEOUT_VAR),
+        );
+        override_with(
+            &mut self.ca_bundle,
+            env_var(CA_BUNDLE_VAR)?.map(PathBuf::from),
+            || Source::Environment(CA_BUNDLE_VAR),
+        );
+        override_with(
+            &mut self.max_attempts,
+            parsed_env_var(MAX_ATTEMPTS_VAR)?,
+            || Source::Environment(MAX_ATTEMPTS_VAR),
+        );
+        override_with(&mut self.base_delay, parsed_env_var(RETRY_DELAY_VAR)?, || {
+            Source::Environment(RETRY_DELAY_VAR)
+        });
+        override_with(
+            &mut self.max_delay,
+            parsed_env_var(RETRY_MAX_DELAY_VAR)?,
+            || Source::Environment(RETRY_MAX_DELAY_VAR),
+        );
+        override_with(&mut self.jitter, parsed_env_var(RETRY_JITTER_VAR)?, || {
+            Source::Environment(RETRY_JITTER_VAR)
+        });
+
+        Ok(())
+    }
+
+    /// Get the api key, running the api key command or reading the api key file if needed.
+    pub fn required_api_key(&self) -> anyhow::Result<String> {
+        match &self.api_key {
+            Some(api_key) => api_key
+                .value
+                .resolve()
+                .with_context(|| format!("failed to get the api key from {}", api_key.source)),
+            None => {
+                tip!(
+                    "add an api key to the configuration file with {}",
+                    "synthtext config generate --api-key <API KEY>".italic()
+                );
+                tip!(
+                    "alternatively, set {} or {} in the configuration file, or set the {} environment variable",
+                    "api_key_command".bold(),
+                    "api_key_file".bold(),
+                    API_KEY_VAR.bold()
+                );
+                anyhow::bail!("no api key was specified")
+            }
+        }
+    }
+
+    /// The sampling defaults, without their sources.
+    pub fn defaults(&self) -> Defaults {
+        Defaults {
+            max_tokens: self.max_tokens.as_ref().map(|sourced| sourced.value),
+            temperature: self.temperature.as_ref().map(|sourced| sourced.value),
+            top_k: self.top_k.as_ref().map(|sourced| sourced.value),
+            top_p: self.top_p.as_ref().map(|sourced| sourced.value),
+            until: self
+                .until
+                .as_ref()
+                .map(|sourced| sourced.value.clone())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// The connection settings, without their sources.
+    pub fn connection(&self) -> Connection {
+        Connection {
+            base_url: self.base_url.as_ref().map(|sourced| sourced.value.clone()),
+            proxy: self.proxy.as_ref().map(|sourced| sourced.value.clone()),
+            timeout: self.timeout.as_ref().map(|sourced| sourced.value),
+            connect_timeout: self.connect_timeout.as_ref().map(|sourced| sourced.value),
+            ca_bundle: self.ca_bundle.as_ref().map(|sourced| sourced.value.clone()),
+        }
+    }
+
+    /// The retry policy, without its sources.
+    pub fn retry(&self) -> Retry {
+        Retry {
+            max_attempts: self.max_attempts.as_ref().map(|sourced| sourced.value),
+            base_delay: self.base_delay.as_ref().map(|sourced| sourced.value),
+            max_delay: self.max_delay.as_ref().map(|sourced| sourced.value),
+            jitter: self.jitter.as_ref().map(|sourced| sourced.value),
+        }
+    }
+}
+
+fn override_with<T>(
+    slot: &mut Option<Sourced<T>>,
+    value: Option<T>,
+    source: impl FnOnce() -> Source,
+) {
+    if let Some(value) = value {
+        *slot = Some(Sourced {
+            value,
+            source: source(),
+        });
+    }
+}
+
+/// Read an environment variable, treating an empty variable the same as an unset one.
+fn env_var(name: &'static str) -> anyhow::Result<Option<String>> {
+    match env::var(name) {
+        Ok(value) if value.is_empty() => Ok(None),
+        Ok(value) => Ok(Some(value)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(error) => Err(error)
+            .with_context(|| format!("failed to read environment variable {}", name.bold())),
+    }
+}
+
+fn parsed_env_var<T>(name: &'static str) -> anyhow::Result<Option<T>>
+where
+    T: FromStr,
+    anyhow::Error: From<T::Err>,
+{
+    env_var(name)?
+        .map(|value| {
+            value.parse::<T>().map_err(anyhow::Error::from).with_context(|| {
+                format!(
+                    "failed to parse environment variable {} (value {})",
+                    name.bold(),
+                    value.bold()
+                )
+            })
+        })
+        .transpose()
+}
+
+fn file_source(layer: &Layer, profile: Option<&str>) -> Source {
+    Source::File {
+        location: layer.location.clone(),
+        profile: profile.map(ToString::to_string),
+    }
+}
+
+fn missing_profile<'a>(name: &str, available: impl Iterator<Item = &'a String>) -> anyhow::Error {
+    let available = available
+        .map(|name| name.bold().to_string())
+        .collect::<Vec<_>>();
+
+    if available.is_empty() {
+        tip!(
+            "add a profile with {}",
+            format_args!("synthtext --profile {name} config generate --api-key <API KEY>").italic()
+        );
+    } else {
+        tip!("available profiles are: {}", available.join(", "));
+    }
+
+    anyhow::anyhow!("the profile {} does not exist", name.bold())
+}
+
+/// Resolve the effective configuration from the config files, from the lowest to the highest
+/// precedence. Within each config file, the selected profile takes precedence over the top level
+/// values.
+pub fn resolve(layers: &[Layer], overrides: Overrides) -> anyhow::Result<Resolved> {
+    let Overrides {
+        profile: profile_override,
+        engine_definition: engine_definition_override,
+        connection: connection_override,
+        retry: retry_override,
+    } = overrides;
+    let mut profile = None;
+
+    for layer in layers {
+        override_with(&mut profile, layer.config.default_profile.clone(), || {
+            file_source(layer, None)
+        });
+    }
+
+    override_with(&mut profile, env_var(PROFILE_VAR)?, || {
+        Source::Environment(PROFILE_VAR)
+    });
+    override_with(&mut profile, profile_override, || Source::Cli("--profile"));
+
+    let name = profile.as_ref().map(|profile| profile.value.as_str());
+    let mut resolved = Resolved::new();
+    let mut found = false;
+
+    for layer in layers {
+        resolved.layer_profile(layer, &layer.config.base, || file_source(layer, None));
+
+        if let Some(named) = name.and_then(|name| layer.config.profiles.get(name)) {
+            found = true;
+            resolved.layer_profile(layer, named, || file_source(layer, name));
+        }
+    }
+
+    if let Some(name) = name {
+        if !found {
+            let available = layers
+                .iter()
+                .flat_map(|layer| layer.config.profiles.keys())
+                .collect::<BTreeSet<_>>();
+            return Err(missing_profile(name, available.into_iter()));
+        }
+    }
+
+    resolved.profile = profile;
+    resolved.layer_environment()?;
+
+    if let Some(engine_definition) = engine_definition_override {
+        resolved.engine_definition = Sourced {
+            value: engine_definition,
+            source: Source::Cli("--engine"),
+        };
+    }
+
+    resolved.layer_cli_connection(connection_override);
+    resolved.layer_cli_retry(retry_override);
+    resolved
+        .defaults()
+        .validate()
+        .context("invalid sampling defaults")?;
+    resolved
+        .connection()
+        .validate()
+        .context("invalid connection settings")?;
+    resolved
+        .retry()
+        .validate()
+        .context("invalid retry policy")?;
+
+    Ok(resolved)
+}
+
+/// Display an engine definition the way it is written in the config file.
+pub fn engine_definition_to_string(engine_definition: &EngineDefinition) -> String {
+    match serde_json::to_value(engine_definition) {
+        Ok(serde_json::Value::String(string)) => string,
+        Ok(value) => value.to_string(),
+        Err(_) => "(unknown)".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        resolve, Overrides, Resolved, ENGINE_VAR, PROFILE_VAR, TEMPERATURE_VAR, TIMEOUT_VAR,
+    };
+    use crate::config::{Config, Connection, Defaults, Layer, Profile};
+    use std::env;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+    use textsynth::prelude::{CustomEngineDefinition, EngineDefinition};
+
+    /// The environment is shared by the tests, which run at the same time.
+    static ENVIRONMENT: Mutex<()> = Mutex::new(());
+
+    /// Resolve the layers with the environment variables set, and every other variable read by
+    /// the tests unset.
+    fn resolve_with(
+        layers: &[Layer],
+        overrides: Overrides,
+        variables: &[(&str, &str)],
+    ) -> anyhow::Result<Resolved> {
+        let _guard = ENVIRONMENT.lock().unwrap_or_else(|error| error.into_inner());
+
+        for name in [PROFILE_VAR, ENGINE_VAR, TEMPERATURE_VAR, TIMEOUT_VAR] {
+            env::remove_var(name);
+        }
+
+        for (name, value) in variables {
+            env::set_var(name, value);
+        }
+
+        let resolved = resolve(layers, overrides);
+
+        for (name, _) in variables {
+            env::remove_var(name);
+        }
+
+        resolved
+    }
+
+    fn profile(engine_definition: EngineDefinition, temperature: f64) -> Profile {
+        Profile {
+            engine_definition: Some(engine_definition),
+            defaults: Defaults {
+                temperature: Some(temperature),
+                ..Defaults::default()
+            },
+            ..Profile::default()
+        }
+    }
+
+    fn custom(id: &str) -> EngineDefinition {
+        EngineDefinition::Custom(CustomEngineDefinition::new(id.to_string(), 1024))
+    }
+
+    fn user_layer() -> Layer {
+        Layer {
+            location: PathBuf::from("/home/user/.config/synthtext/config.toml"),
+            config: Config {
+                base: profile(EngineDefinition::Boris6B, 0.1),
+                ..Config::default()
+            },
+            project: false,
+        }
+    }
+
+    fn project_layer() -> Layer {
+        Layer {
+            location: PathBuf::from("/project/.synthtext.toml"),
+            config: Config {
+                base: profile(EngineDefinition::FairseqGpt13B, 0.2),
+                ..Config::default()
+            },
+            project: true,
+        }
+    }
+
+    fn engine_id(resolved: &Resolved) -> &str {
+        crate::textsynth::engine_id(&resolved.engine_definition.value)
+    }
+
+    #[test]
+    fn built_in_defaults() {
+        let resolved = resolve_with(&[], Overrides::default(), &[]).unwrap();
+
+        assert_eq!(engine_id(&resolved), "gptj_6B");
+        assert_eq!(
+            resolved.engine_definition.source.to_string(),
+            "built-in default"
+        );
+        assert!(resolved.temperature.is_none());
+        assert!(resolved.profile.is_none());
+    }
+
+    #[test]
+    fn user_file_over_defaults() {
+        let resolved = resolve_with(&[user_layer()], Overrides::default(), &[]).unwrap();
+        let temperature = resolved.temperature.as_ref().unwrap();
+
+        assert_eq!(engine_id(&resolved), "boris_6B");
+        assert_eq!(
+            resolved.engine_definition.source.to_string(),
+            "config file /home/user/.config/synthtext/config.toml"
+        );
+        assert_eq!(temperature.value, 0.1);
+        assert_eq!(
+            temperature.source.to_string(),
+            "config file /home/user/.config/synthtext/config.toml"
+        );
+    }
+
+    #[test]
+    fn project_files_over_the_user_file() {
+        let mut nested = project_layer();
+        nested.location = PathBuf::from("/project/nested/.synthtext.toml");
+        nested.config.base = Profile {
+            defaults: Defaults {
+                temperature: Some(0.3),
+                ..Defaults::default()
+            },
+            ..Profile::default()
+        };
+        let layers = [user_layer(), project_layer(), nested];
+        let resolved = resolve_with(&layers, Overrides::default(), &[]).unwrap();
+        let temperature = resolved.temperature.as_ref().unwrap();
+
+        // values which the closest project file doesn't set come from the next one
+        assert_eq!(engine_id(&resolved), "fairseq_gpt_13B");
+        assert_eq!(
+            resolved.engine_definition.source.to_string(),
+            "config file /project/.synthtext.toml"
+        );
+        assert_eq!(temperature.value, 0.3);
+        assert_eq!(
+            temperature.source.to_string(),
+            "config file /project/nested/.synthtext.toml"
+        );
+    }
+
+    #[test]
+    fn project_files_cant_redirect_the_connection() {
+        let mut project = project_layer();
+        project.config.base.connection = Connection {
+            base_url: Some("https://example.com".to_string()),
+            timeout: Some(5.0),
+            ..Connection::default()
+        };
+        let resolved = resolve_with(&[user_layer(), project], Overrides::default(), &[]).unwrap();
+
+        assert!(resolved.base_url.is_none());
+        assert_eq!(resolved.timeout.as_ref().unwrap().value, 5.0);
+    }
+
+    #[test]
+    fn profile_over_the_top_level_of_its_file() {
+        let mut user = user_layer();
+        user.config.default_profile = Some("work".to_string());
+        user.config
+            .profiles
+            .insert("work".to_string(), profile(custom("work_engine"), 0.4));
+        let resolved = resolve_with(&[user], Overrides::default(), &[]).unwrap();
+        let profile = resolved.profile.as_ref().unwrap();
+
+        assert_eq!(profile.value, "work");
+        assert_eq!(
+            profile.source.to_string(),
+            "config file /home/user/.config/synthtext/config.toml"
+        );
+        assert_eq!(engine_id(&resolved), "work_engine");
+        assert_eq!(
+            resolved.engine_definition.source.to_string(),
+            "profile work of config file /home/user/.config/synthtext/config.toml"
+        );
+    }
+
+    #[test]
+    fn environment_over_files() {
+        let resolved = resolve_with(
+            &[user_layer(), project_layer()],
+            Overrides::default(),
+            &[(ENGINE_VAR, "env_engine,512"), (TEMPERATURE_VAR, "0.7")],
+        )
+        .unwrap();
+        let temperature = resolved.temperature.as_ref().unwrap();
+
+        assert_eq!(engine_id(&resolved), "env_engine");
+        assert_eq!(
+            resolved.engine_definition.source.to_string(),
+            "environment variable SYNTHTEXT_ENGINE"
+        );
+        assert_eq!(temperature.value, 0.7);
+        assert_eq!(
+            temperature.source.to_string(),
+            "environment variable SYNTHTEXT_TEMPERATURE"
+        );
+    }
+
+    #[test]
+    fn command_line_over_environment() {
+        let overrides = Overrides {
+            engine_definition: Some(custom("cli_engine")),
+            connection: Connection {
+                timeout: Some(10.0),
+                ..Connection::default()
+            },
+            ..Overrides::default()
+        };
+        let resolved = resolve_with(
+            &[user_layer()],
+            overrides,
+            &[(ENGINE_VAR, "env_engine,512"), (TIMEOUT_VAR, "20")],
+        )
+        .unwrap();
+        let timeout = resolved.timeout.as_ref().unwrap();
+
+        assert_eq!(engine_id(&resolved), "cli_engine");
+        assert_eq!(
+            resolved.engine_definition.source.to_string(),
+            "command line flag --engine"
+        );
+        assert_eq!(timeout.value, 10.0);
+        assert_eq!(timeout.source.to_string(), "command line flag --timeout");
+    }
+
+    #[test]
+    fn profile_from_the_command_line_over_environment_and_files() {
+        let mut user = user_layer();
+        user.config.default_profile = Some("work".to_string());
+
+        for name in ["work", "home", "cli"] {
+            user.config
+                .profiles
+                .insert(name.to_string(), profile(custom(name), 0.5));
+        }
+
+        let overrides = Overrides {
+            profile: Some("cli".to_string()),
+            ..Overrides::default()
+        };
+        let resolved = resolve_with(&[user], overrides, &[(PROFILE_VAR, "home")]).unwrap();
+        let profile = resolved.profile.as_ref().unwrap();
+
+        assert_eq!(profile.value, "cli");
+        assert_eq!(profile.source.to_string(), "command line flag --profile");
+        assert_eq!(engine_id(&resolved), "cli");
+    }
+
+    #[test]
+    fn missing_profile() {
+        let overrides = Overrides {
+            profile: Some("missing".to_string()),
+            ..Overrides::default()
+        };
+
+        assert!(resolve_with(&[user_layer()], overrides, &[]).is_err());
+    }
+}
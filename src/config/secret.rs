@@ -0,0 +1,92 @@
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::{fmt, fs};
+
+/// Where the api key is taken from. Keys from commands and files are only read when they're
+/// actually needed, which is right before the textsynth client is initialized.
+#[derive(Clone)]
+pub enum ApiKey {
+    Plain(String),
+
+    /// A shell command whose standard output is the api key, for example a password manager.
+    Command(String),
+
+    /// A file whose contents are the api key.
+    File(PathBuf),
+}
+
+impl ApiKey {
+    pub fn resolve(&self) -> anyhow::Result<String> {
+        match self {
+            Self::Plain(api_key) => Ok(api_key.clone()),
+            Self::Command(command) => run(command),
+            Self::File(path) => {
+                let contents = fs::read_to_string(path).with_context(|| {
+                    format!("failed to read the api key file {}", path.display().bold())
+                })?;
+                let api_key = contents.trim();
+
+                if api_key.is_empty() {
+                    anyhow::bail!("the api key file {} is empty", path.display().bold())
+                }
+
+                Ok(api_key.to_string())
+            }
+        }
+    }
+}
+
+impl fmt::Display for ApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Plain(api_key) => write!(f, "{}", super::redact(api_key)),
+            Self::Command(command) => write!(f, "output of command `{command}`"),
+            Self::File(path) => write!(f, "contents of file {}", path.display()),
+        }
+    }
+}
+
+fn shell(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C").arg(command);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c").arg(command);
+        shell
+    }
+}
+
+fn run(command: &str) -> anyhow::Result<String> {
+    // standard error is inherited so that password managers can still ask for a passphrase
+    let output = shell(command)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| format!("failed to run the api key command {}", command.bold()))?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "the api key command {} failed ({})",
+            command.bold(),
+            output.status
+        )
+    }
+
+    let stdout = String::from_utf8(output.stdout).with_context(|| {
+        format!(
+            "the output of the api key command {} wasn't valid utf-8",
+            command.bold()
+        )
+    })?;
+    let api_key = stdout.trim();
+
+    if api_key.is_empty() {
+        anyhow::bail!("the api key command {} printed nothing", command.bold())
+    }
+
+    Ok(api_key.to_string())
+}
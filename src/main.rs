@@ -1,23 +1,38 @@
 mod app;
 mod args;
 mod config;
+mod output;
+mod retry;
+mod template;
 mod textsynth;
+mod verbosity;
 
 use anyhow::Context;
 use args::*;
 use owo_colors::OwoColorize;
 use std::process;
-use tap::Pipe;
+use std::time::Instant;
+use verbosity::{error, verbose};
 
 #[tokio::main]
 async fn main() {
     async fn inner() -> anyhow::Result<()> {
         let args = args::parse();
+        verbosity::initialize(verbosity::Level::from_flags(args.quiet, args.verbose));
 
         config::paths::initialize().context("failed to initialize config paths")?;
+        config::permissions::set_strict(args.strict_permissions);
+        output::set(args.output);
 
-        if !matches!(args.action, SynthTextAction::Config(_)) {
-            let config = match args.config {
+        let overrides = args.overrides();
+
+        if !matches!(
+            args.action,
+            SynthTextAction::Config(_) | SynthTextAction::Template(_) | SynthTextAction::Doctor
+        ) {
+            let start = Instant::now();
+
+            match args.config {
                 Some(ref config_path) => config::initialize_with_location(config_path)
                     .with_context(|| {
                         format!(
@@ -29,25 +44,86 @@ async fn main() {
                     .context("failed to initialize the config with the default location")?,
             };
 
-            textsynth::initialize(config.api_key.clone())?;
+            let resolved = config::resolve(overrides.clone())
+                .context("failed to resolve the configuration")?;
+            verbose!(
+                "loaded {} config file(s) in {} ms; using engine {}",
+                config::layers().len(),
+                start.elapsed().as_millis(),
+                config::resolve::engine_definition_to_string(&resolved.engine_definition.value)
+            );
+
+            textsynth::initialize(resolved.required_api_key()?, &resolved.connection())?;
         }
 
         match args.action {
             SynthTextAction::LogProbabilities {
                 context,
                 continuation,
+                ..
             } => app::log_probabilities(context, continuation).await,
             SynthTextAction::TextCompletion {
                 prompt,
+                template,
+                vars,
+                vars_file,
+                prompt_file,
+                separator,
+                prefix,
+                suffix,
                 max_tokens,
                 temperature,
                 top_k,
                 top_p,
+                echo,
+                no_echo,
                 method,
-            } => app::text_completion(prompt, max_tokens, temperature, top_k, top_p, method).await,
+                ..
+            } => {
+                let template = template.map(|name| template::Invocation {
+                    name,
+                    variables: vars
+                        .into_iter()
+                        .map(|VariableFromStrAdapter(key, value)| (key, value))
+                        .collect(),
+                    variables_file: vars_file,
+                });
+                let prompt =
+                    PromptSources::new(template, prompt, prompt_file, separator, prefix, suffix);
+                let echo = if no_echo { Echo::None } else { echo };
+                app::text_completion(prompt, max_tokens, temperature, top_k, top_p, echo, method)
+                    .await
+            }
             SynthTextAction::Config(config) => match config {
-                #[allow(clippy::unit_arg)]
-                SynthTextConfig::FindPath => app::config::find_path(args.config).pipe(Ok),
+                SynthTextConfig::FindPath => app::config::find_path(args.config),
+
+                SynthTextConfig::Show { resolved } => {
+                    app::config::show(args.config, overrides, resolved)
+                }
+
+                SynthTextConfig::Validate { online } => {
+                    app::config::validate(args.config, overrides, online).await
+                }
+
+                SynthTextConfig::Convert { path, to, keep } => {
+                    app::config::convert(args.config, path, to, keep)
+                }
+
+                SynthTextConfig::Migrate { path } => app::config::migrate(args.config, path),
+
+                SynthTextConfig::Init { path } => {
+                    app::config::init(args.config, args.profile, path)
+                }
+
+                SynthTextConfig::Get { key } => app::config::get(args.config, args.profile, key),
+
+                SynthTextConfig::Set { key, value } => {
+                    app::config::set(args.config, args.profile, key, value)
+                }
+
+                SynthTextConfig::Unset { key } => {
+                    app::config::unset(args.config, args.profile, key)
+                }
 
                 SynthTextConfig::Generate {
                     path,
@@ -55,22 +131,42 @@ async fn main() {
                     engine_definition,
                     dump,
                     create,
+                    make_default,
+                    encrypt,
+                    format,
                 } => app::config::generate(
                     args.config,
+                    args.profile,
                     path,
                     api_key,
                     engine_definition,
                     dump,
                     create,
+                    make_default,
+                    encrypt,
+                    format,
                 ),
             },
+            SynthTextAction::Batch(batch) => app::batch::run(batch).await,
+            SynthTextAction::Repl { .. } => app::repl::run().await,
+            SynthTextAction::Template(template) => match template {
+                SynthTextTemplate::List => app::template::list(),
+                SynthTextTemplate::Show { name } => app::template::show(&name),
+                SynthTextTemplate::Add {
+                    name,
+                    source,
+                    force,
+                } => app::template::add(&name, source, force),
+                SynthTextTemplate::Rm { name } => app::template::remove(&name),
+            },
+            SynthTextAction::Doctor => app::doctor(args.config, overrides).await,
         }
     }
 
     let exit_code = match inner().await {
         Ok(_) => 0,
         Err(err) => {
-            alp::error!("{:#}", err);
+            error!("{:#}", err);
             1
         }
     };
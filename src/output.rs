@@ -0,0 +1,75 @@
+//! How results are written to stdout.
+
+use anyhow::Context;
+use once_cell::sync::OnceCell;
+use owo_colors::OwoColorize;
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+
+static OUTPUT: OnceCell<Output> = OnceCell::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    /// Human readable text, with colors.
+    Text,
+
+    /// A single json document.
+    Json,
+
+    /// One json document per line, such as one per chunk of a stream.
+    Ndjson,
+}
+
+impl Output {
+    pub fn is_text(self) -> bool {
+        self == Self::Text
+    }
+
+    /// Write the value to stdout as a json document, on a single line for ndjson.
+    pub fn emit(self, value: &impl Serialize) -> anyhow::Result<()> {
+        let document = match self {
+            Self::Ndjson => serde_json::to_string(value),
+            Self::Json | Self::Text => serde_json::to_string_pretty(value),
+        }
+        .context("failed to serialize output to json")?;
+
+        println!("{document}");
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Text => "text",
+            Self::Json => "json",
+            Self::Ndjson => "ndjson",
+        })
+    }
+}
+
+impl FromStr for Output {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "ndjson" => Ok(Self::Ndjson),
+            _ => anyhow::bail!(
+                "unknown output {}; expected one of json, ndjson or text",
+                s.bold()
+            ),
+        }
+    }
+}
+
+pub fn set(output: Output) {
+    let _ = OUTPUT.set(output);
+}
+
+pub fn get() -> Output {
+    OUTPUT.get().copied().unwrap_or(Output::Text)
+}
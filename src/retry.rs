@@ -0,0 +1,152 @@
+//! Retrying failed requests with exponential backoff.
+//!
+//! Only transport errors, timeouts and HTTP 408, 429 and 5xx responses are retried. When the api
+//! answers with a `Retry-After` header, it is waited for instead of the backoff; if it asks to wait
+//! longer than the maximum delay, the request fails instead.
+
+use crate::config::Retry;
+use crate::textsynth::ApiError;
+use crate::verbosity::{debug, verbose};
+use reqwest::StatusCode;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+pub struct Policy {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: f64,
+}
+
+impl Policy {
+    pub fn new(retry: &Retry) -> anyhow::Result<Self> {
+        Ok(Self {
+            max_attempts: retry.max_attempts()?,
+            base_delay: retry.base_delay()?,
+            max_delay: retry.max_delay()?,
+            jitter: retry.jitter()?,
+        })
+    }
+
+    /// The policy of the resolved configuration.
+    pub fn resolved() -> anyhow::Result<Self> {
+        Self::new(&crate::config::resolved().retry())
+    }
+
+    /// How long to wait before the next attempt, if the attempt which failed with the error
+    /// should be retried. Attempts are counted from 1.
+    pub fn delay(&self, attempt: u32, error: &anyhow::Error) -> Option<Duration> {
+        if attempt >= self.max_attempts || !is_retryable(error) {
+            return None;
+        }
+
+        if let Some(retry_after) = retry_after(error) {
+            if retry_after > self.max_delay {
+                debug!(
+                    "the api asked to wait {:.1} seconds, which is longer than the maximum delay",
+                    retry_after.as_secs_f64()
+                );
+                return None;
+            }
+
+            return Some(retry_after);
+        }
+
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt - 1))
+            .min(self.max_delay);
+        Some(delay.mul_f64(1.0 - self.jitter * rand::random::<f64>()))
+    }
+
+    /// Run the request until it succeeds, it fails with an error which shouldn't be retried, or
+    /// the maximum number of attempts is reached.
+    pub async fn run<T, F, Fut>(&self, name: &str, mut request: F) -> anyhow::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let started = Instant::now();
+        let mut attempt = 1;
+
+        loop {
+            let start = Instant::now();
+            let result = request().await;
+            debug!(
+                "{} attempt {}/{} took {} ms",
+                name,
+                attempt,
+                self.max_attempts,
+                start.elapsed().as_millis()
+            );
+
+            match result {
+                Ok(value) => {
+                    verbose!(
+                        "{} took {} ms in {} attempt(s)",
+                        name,
+                        started.elapsed().as_millis(),
+                        attempt
+                    );
+                    return Ok(value);
+                }
+                Err(error) => match self.delay(attempt, &error) {
+                    Some(delay) => {
+                        self.wait(name, attempt, &error, delay).await;
+                        attempt += 1;
+                    }
+                    None => return Err(error),
+                },
+            }
+        }
+    }
+
+    /// Log the failed attempt and wait for the delay before the next one.
+    pub async fn wait(&self, name: &str, attempt: u32, error: &anyhow::Error, delay: Duration) {
+        debug!(
+            "{} attempt {}/{} failed, retrying in {:.1} seconds: {:#}",
+            name,
+            attempt,
+            self.max_attempts,
+            delay.as_secs_f64(),
+            error
+        );
+        tokio::time::sleep(delay).await
+    }
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status.is_server_error()
+}
+
+/// Whether the error is likely to go away by itself.
+fn is_retryable(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        if cause.is::<tokio::time::error::Elapsed>() {
+            return true;
+        }
+
+        if let Some(error) = cause.downcast_ref::<ApiError>() {
+            return is_retryable_status(error.status);
+        }
+
+        match cause.downcast_ref::<reqwest::Error>() {
+            Some(error) => match error.status() {
+                Some(status) => is_retryable_status(status),
+                None => {
+                    error.is_timeout() || error.is_connect() || error.is_request() || error.is_body()
+                }
+            },
+            None => false,
+        }
+    })
+}
+
+/// How long the api asked to wait before trying again, if it did.
+fn retry_after(error: &anyhow::Error) -> Option<Duration> {
+    error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<ApiError>()?.retry_after)
+}
@@ -0,0 +1,560 @@
+//! Prompt templates, kept in the data directory.
+//!
+//! A template is a prompt with `{{variable}}` slots and `{{> partial}}` includes of other
+//! templates. It can start with a yaml front matter between two `---` lines, holding a
+//! description, default values for its variables and default sampling parameters:
+//!
+//! ```text
+//! ---
+//! description: Answer a question from a few examples
+//! temperature: 0.7
+//! until: ["\n\n"]
+//! variables:
+//!   language: English
+//! ---
+//! {{> examples}}
+//! Question ({{language}}): {{question}}
+//! Answer:
+//! ```
+//!
+//! A backslash before `{{` writes the braces as is.
+
+use crate::config::{paths, Defaults};
+use crate::verbosity::tip;
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
+
+/// Variables which aren't given on the command line or in a variables file are read from the
+/// environment variable with this prefix, followed by the name of the variable in uppercase.
+pub const VAR_PREFIX: &str = "SYNTHTEXT_VAR_";
+
+const EXTENSION: &str = "txt";
+const FRONT_MATTER_DELIMITER: &str = "---";
+
+/// The yaml block at the start of a template.
+#[derive(Default, Serialize, Deserialize)]
+pub struct FrontMatter {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    /// The values of the variables which aren't given any other way.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub variables: BTreeMap<String, String>,
+
+    /// Sampling parameters which are used when they weren't passed on the command line. They take
+    /// precedence over the defaults of the configuration.
+    #[serde(flatten)]
+    pub defaults: Defaults,
+}
+
+pub struct Template {
+    pub name: String,
+    pub front_matter: FrontMatter,
+    pub body: String,
+}
+
+impl Template {
+    /// Parse the source of a template, checking the front matter and the syntax of the body.
+    pub fn parse(name: &str, source: &str) -> anyhow::Result<Self> {
+        let (front_matter, body) = split_front_matter(source);
+        let front_matter = match front_matter {
+            Some(front_matter) if !front_matter.trim().is_empty() => {
+                serde_yaml::from_str::<FrontMatter>(front_matter)
+                    .context("failed to parse the front matter")?
+            }
+            _ => FrontMatter::default(),
+        };
+
+        front_matter
+            .defaults
+            .validate()
+            .context("invalid front matter")?;
+        segments(body)?;
+
+        Ok(Self {
+            name: name.to_string(),
+            front_matter,
+            body: body.to_string(),
+        })
+    }
+
+    /// Load the template with the specified name from the template directory.
+    pub fn load(name: &str) -> anyhow::Result<Self> {
+        let path = path(name)?;
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                tip!(
+                    "run {} to see the available templates",
+                    "synthtext template list".italic()
+                );
+                anyhow::bail!("there is no template named {}", name.bold())
+            }
+            Err(error) => {
+                return Err(error).with_context(|| {
+                    format!("failed to read template {}", path.display().bold())
+                })
+            }
+        };
+
+        Self::parse(name, &source).with_context(|| format!("invalid template {}", name.bold()))
+    }
+
+    /// The names of the variables used by the template, not counting its partials.
+    pub fn variables(&self) -> BTreeSet<String> {
+        segments(&self.body)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|segment| match segment {
+                Segment::Variable(name) => Some(name.to_string()),
+                Segment::Text(_) | Segment::Partial(_) => None,
+            })
+            .collect()
+    }
+
+    /// Fill in the variables and the partials. Every variable which has no value is reported at
+    /// once.
+    pub fn render(&self, variables: &Variables) -> anyhow::Result<String> {
+        self.render_from(variables, &Self::load)
+    }
+
+    /// Like [`Template::render`], loading the partials with the function.
+    fn render_from(
+        &self,
+        variables: &Variables,
+        load: &dyn Fn(&str) -> anyhow::Result<Self>,
+    ) -> anyhow::Result<String> {
+        let mut missing = BTreeSet::new();
+        let rendered = self.render_with(
+            variables,
+            &self.front_matter.variables,
+            load,
+            &mut vec![self.name.clone()],
+            &mut missing,
+        )?;
+
+        if !missing.is_empty() {
+            tip!(
+                "pass them with {}, in a file with {} or as {} environment variables",
+                "--var key=value".italic(),
+                "--vars-file".italic(),
+                format!("{VAR_PREFIX}KEY").italic()
+            );
+            anyhow::bail!(
+                "template {} is missing the variable(s) {}",
+                self.name.bold(),
+                missing.into_iter().collect::<Vec<_>>().join(", ").bold()
+            )
+        }
+
+        Ok(rendered)
+    }
+
+    /// Render the body with the default values of the variables merged from this template and
+    /// the ones including it, where the including templates take precedence.
+    fn render_with(
+        &self,
+        variables: &Variables,
+        defaults: &BTreeMap<String, String>,
+        load: &dyn Fn(&str) -> anyhow::Result<Self>,
+        including: &mut Vec<String>,
+        missing: &mut BTreeSet<String>,
+    ) -> anyhow::Result<String> {
+        let mut rendered = String::new();
+
+        for segment in segments(&self.body)? {
+            match segment {
+                Segment::Text(text) => rendered.push_str(text),
+                Segment::Variable(name) => {
+                    match variables.get(name).or_else(|| defaults.get(name).cloned()) {
+                        Some(value) => rendered.push_str(&value),
+                        None => {
+                            missing.insert(name.to_string());
+                        }
+                    }
+                }
+                Segment::Partial(name) => {
+                    if including.iter().any(|including| including == name) {
+                        anyhow::bail!(
+                            "template {} includes itself through {}",
+                            name.bold(),
+                            including.join(" -> ")
+                        )
+                    }
+
+                    let partial = load(name)?;
+                    let mut merged = partial.front_matter.variables.clone();
+                    merged.extend(defaults.clone());
+
+                    including.push(name.to_string());
+                    rendered.push_str(
+                        &partial.render_with(variables, &merged, load, including, missing)?,
+                    );
+                    including.pop();
+                }
+            }
+        }
+
+        Ok(rendered)
+    }
+}
+
+/// The values of the variables given on the command line and in a variables file. The environment
+/// is looked at for the variables which aren't given here.
+#[derive(Default)]
+pub struct Variables(BTreeMap<String, String>);
+
+impl Variables {
+    /// Read the variables file, a json object, then let the variables given on the command line
+    /// take precedence.
+    pub fn new(given: Vec<(String, String)>, file: Option<&Path>) -> anyhow::Result<Self> {
+        let mut variables = BTreeMap::new();
+
+        if let Some(file) = file {
+            let source = fs::read_to_string(file).with_context(|| {
+                format!("failed to read variables file {}", file.display().bold())
+            })?;
+            let values = serde_json::from_str::<BTreeMap<String, serde_json::Value>>(&source)
+                .with_context(|| {
+                    format!(
+                        "variables file {} must be a json object",
+                        file.display().bold()
+                    )
+                })?;
+
+            for (name, value) in values {
+                let value = match value {
+                    serde_json::Value::String(value) => value,
+                    value => value.to_string(),
+                };
+
+                variables.insert(name, value);
+            }
+        }
+
+        variables.extend(given);
+
+        Ok(Self(variables))
+    }
+
+    fn get(&self, name: &str) -> Option<String> {
+        self.0
+            .get(name)
+            .cloned()
+            .or_else(|| env::var(format!("{VAR_PREFIX}{}", name.to_uppercase())).ok())
+    }
+}
+
+/// A template to render into the prompt of a text completion.
+#[derive(Debug)]
+pub struct Invocation {
+    pub name: String,
+    pub variables: Vec<(String, String)>,
+    pub variables_file: Option<PathBuf>,
+}
+
+impl Invocation {
+    /// Render the template, returning the prompt and the sampling defaults of the template.
+    pub fn render(self) -> anyhow::Result<(String, Defaults)> {
+        let template = Template::load(&self.name)?;
+        let variables = Variables::new(self.variables, self.variables_file.as_deref())?;
+        let prompt = template.render(&variables)?;
+
+        Ok((prompt, template.front_matter.defaults))
+    }
+}
+
+/// The file the template with the specified name is kept in.
+pub fn path(name: &str) -> anyhow::Result<PathBuf> {
+    check_name(name)?;
+
+    Ok(paths::templates_directory().join(format!("{name}.{EXTENSION}")))
+}
+
+/// The names of every template in the template directory, sorted.
+pub fn list() -> anyhow::Result<Vec<String>> {
+    let directory = paths::templates_directory();
+
+    if !directory.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut names = fs::read_dir(&directory)
+        .with_context(|| {
+            format!(
+                "failed to read the template directory {}",
+                directory.display().bold()
+            )
+        })?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().and_then(|extension| extension.to_str()) == Some(EXTENSION))
+        .filter_map(|path| Some(path.file_stem()?.to_str()?.to_string()))
+        .filter(|name| check_name(name).is_ok())
+        .collect::<Vec<_>>();
+    names.sort();
+
+    Ok(names)
+}
+
+/// Template names are used as file names, so they're kept to letters, digits, `-` and `_`.
+fn check_name(name: &str) -> anyhow::Result<&str> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|char| char.is_alphanumeric() || char == '-' || char == '_')
+    {
+        anyhow::bail!(
+            "invalid template name {}; only letters, digits, - and _ are allowed",
+            name.bold()
+        )
+    }
+
+    Ok(name)
+}
+
+/// Variable names can be read from environment variables, so they're kept to letters, digits and
+/// `_`.
+fn check_variable(name: &str) -> anyhow::Result<&str> {
+    if name.is_empty() || !name.chars().all(|char| char.is_alphanumeric() || char == '_') {
+        anyhow::bail!(
+            "invalid variable name {}; only letters, digits and _ are allowed",
+            name.bold()
+        )
+    }
+
+    Ok(name)
+}
+
+/// Split the front matter from the body. Without a closing delimiter, there is no front matter.
+fn split_front_matter(source: &str) -> (Option<&str>, &str) {
+    let source = source.strip_prefix('\u{feff}').unwrap_or(source);
+    let mut lines = source.split_inclusive('\n');
+
+    match lines.next() {
+        Some(line) if line.trim_end() == FRONT_MATTER_DELIMITER => {}
+        _ => return (None, source),
+    }
+
+    let start = source.len() - lines.clone().map(str::len).sum::<usize>();
+    let mut end = start;
+
+    for line in lines {
+        if line.trim_end() == FRONT_MATTER_DELIMITER {
+            return (Some(&source[start..end]), &source[end + line.len()..]);
+        }
+
+        end += line.len();
+    }
+
+    (None, source)
+}
+
+enum Segment<'a> {
+    Text(&'a str),
+    Variable(&'a str),
+    Partial(&'a str),
+}
+
+/// Split the body of a template into text, variables and partials.
+fn segments(body: &str) -> anyhow::Result<Vec<Segment<'_>>> {
+    let mut segments = Vec::new();
+    let mut rest = body;
+
+    while let Some(start) = rest.find("{{") {
+        if rest[..start].ends_with('\\') {
+            segments.push(Segment::Text(&rest[..start - 1]));
+            segments.push(Segment::Text("{{"));
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        segments.push(Segment::Text(&rest[..start]));
+
+        let line = body[..body.len() - rest.len() + start].matches('\n').count() + 1;
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .with_context(|| format!("unclosed {} on line {}", "{{".bold(), line))?;
+        let inner = after[..end].trim();
+        let segment = match inner.strip_prefix('>') {
+            Some(name) => check_name(name.trim()).map(Segment::Partial),
+            None => check_variable(inner).map(Segment::Variable),
+        }
+        .with_context(|| format!("invalid placeholder on line {}", line))?;
+
+        segments.push(segment);
+        rest = &after[end + 2..];
+    }
+
+    segments.push(Segment::Text(rest));
+
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{segments, split_front_matter, Segment, Template, Variables};
+    use std::collections::BTreeMap;
+
+    /// Render the template, loading its partials from the sources instead of the template
+    /// directory.
+    fn render(
+        source: &str,
+        partials: &[(&str, &str)],
+        variables: &[(&str, &str)],
+    ) -> anyhow::Result<String> {
+        let partials = partials.iter().copied().collect::<BTreeMap<_, _>>();
+        let load = |name: &str| match partials.get(name) {
+            Some(source) => Template::parse(name, source),
+            None => anyhow::bail!("there is no template named {}", name),
+        };
+        let variables = variables
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+
+        Template::parse("main", source)?.render_from(&Variables(variables), &load)
+    }
+
+    #[test]
+    fn front_matter_is_split_from_the_body() {
+        assert_eq!(
+            split_front_matter("---\ndescription: test\n---\nbody\n"),
+            (Some("description: test\n"), "body\n")
+        );
+    }
+
+    #[test]
+    fn front_matter_with_crlf_and_a_byte_order_mark() {
+        assert_eq!(
+            split_front_matter("\u{feff}---\r\ndescription: test\r\n---\r\nbody"),
+            (Some("description: test\r\n"), "body")
+        );
+    }
+
+    #[test]
+    fn empty_front_matter() {
+        assert_eq!(split_front_matter("---\n---\nbody"), (Some(""), "body"));
+    }
+
+    #[test]
+    fn unclosed_front_matter_is_part_of_the_body() {
+        assert_eq!(
+            split_front_matter("---\ndescription: test\nbody"),
+            (None, "---\ndescription: test\nbody")
+        );
+    }
+
+    #[test]
+    fn delimiter_after_the_first_line_is_not_front_matter() {
+        assert_eq!(split_front_matter("body\n---\n"), (None, "body\n---\n"));
+    }
+
+    #[test]
+    fn escaped_braces_are_written_as_is() {
+        assert_eq!(
+            render("\\{{name}} is {{name}}", &[], &[("name", "value")]).unwrap(),
+            "{{name}} is value"
+        );
+    }
+
+    #[test]
+    fn escaped_braces_are_not_checked() {
+        assert!(segments("\\{{ not a variable").is_ok());
+        assert!(segments("{{ not a variable").is_err());
+    }
+
+    #[test]
+    fn closing_braces_are_written_as_is() {
+        assert_eq!(
+            render("}} {{a}} }}", &[], &[("a", "b")]).unwrap(),
+            "}} b }}"
+        );
+    }
+
+    #[test]
+    fn placeholders_are_trimmed() {
+        let segments = segments("{{ name }}{{>  partial }}").unwrap();
+
+        assert!(matches!(
+            segments[..],
+            [
+                Segment::Text(""),
+                Segment::Variable("name"),
+                Segment::Text(""),
+                Segment::Partial("partial"),
+                Segment::Text(""),
+            ]
+        ));
+    }
+
+    #[test]
+    fn partials_are_rendered_recursively() {
+        let rendered = render(
+            "a {{> b}} a",
+            &[("b", "b {{> c}} b"), ("c", "c {{name}} c")],
+            &[("name", "value")],
+        )
+        .unwrap();
+
+        assert_eq!(rendered, "a b c value c b a");
+    }
+
+    #[test]
+    fn partials_use_the_defaults_of_the_templates_including_them() {
+        let rendered = render(
+            "---\nvariables:\n  language: French\n---\n{{> question}}",
+            &[(
+                "question",
+                "---\nvariables:\n  language: English\n  style: short\n---\n{{language}} {{style}}",
+            )],
+            &[],
+        )
+        .unwrap();
+
+        assert_eq!(rendered, "French short");
+    }
+
+    #[test]
+    fn given_variables_take_precedence_over_defaults() {
+        let rendered = render(
+            "---\nvariables:\n  language: French\n---\n{{> question}}",
+            &[("question", "{{language}}")],
+            &[("language", "German")],
+        )
+        .unwrap();
+
+        assert_eq!(rendered, "German");
+    }
+
+    #[test]
+    fn partial_including_itself() {
+        let error = render("{{> a}}", &[("a", "{{> b}}"), ("b", "{{> a}}")], &[]).unwrap_err();
+
+        assert!(format!("{:#}", error).contains("includes itself through main -> a -> b"));
+    }
+
+    #[test]
+    fn template_including_itself() {
+        let error = render("{{> main}}", &[("main", "{{> main}}")], &[]).unwrap_err();
+
+        assert!(format!("{:#}", error).contains("includes itself"));
+    }
+
+    #[test]
+    fn same_partial_included_twice_is_not_a_cycle() {
+        assert_eq!(render("{{> a}}{{> a}}", &[("a", "x")], &[]).unwrap(), "xx");
+    }
+
+    #[test]
+    fn missing_variables_are_reported_at_once() {
+        let error = render("{{first}} {{> a}}", &[("a", "{{second}}")], &[]).unwrap_err();
+
+        assert!(format!("{:#}", error).contains("first, second"));
+    }
+}
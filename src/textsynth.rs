@@ -1,22 +1,591 @@
+//! The client for the textsynth api. The types of the textsynth crate are used to check the
+//! parameters, but its client always connects to the official api with an http client of its own,
+//! so the requests are made here instead, with an http client built from the connection settings.
+
 use crate::config;
+use crate::config::Connection;
+use crate::verbosity::debug;
 use anyhow::Context;
 use once_cell::sync::{Lazy, OnceCell};
-use textsynth::core::TextSynth;
-use textsynth::engine::Engine;
+use owo_colors::OwoColorize;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use textsynth::prelude::EngineDefinition;
+
+/// The base url of the official textsynth api.
+pub const DEFAULT_BASE_URL: &str = "https://api.textsynth.com";
+
+static CLIENT: OnceCell<Client> = OnceCell::new();
+static ENGINE: Lazy<RwLock<Arc<Engine<'static>>>> = Lazy::new(|| {
+    RwLock::new(Arc::new(
+        get().engine(config::resolved().engine_definition.value.clone()),
+    ))
+});
+
+/// Build an http client with the proxy, the certificate authorities and the connect timeout of
+/// the connection settings. The request timeout is applied to each request instead, since a
+/// stream may take longer than it as a whole.
+pub fn http_client(connection: &Connection) -> anyhow::Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder();
+
+    if let Some(connect_timeout) = connection.connect_timeout()? {
+        builder = builder.connect_timeout(connect_timeout);
+    }
+
+    if let Some(proxy) = &connection.proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy).context("invalid proxy")?);
+    }
+
+    if let Some(ca_bundle) = &connection.ca_bundle {
+        let contents = fs::read(ca_bundle)
+            .with_context(|| format!("failed to read path {}", ca_bundle.display().bold()))?;
+        let certificate = reqwest::Certificate::from_pem(&contents)
+            .with_context(|| format!("{} is not a pem file", ca_bundle.display().bold()))?;
+        builder = builder.add_root_certificate(certificate);
+    }
+
+    builder.build().context("failed to build the http client")
+}
+
+/// An error the api answered with.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+
+    /// How long the api asked to wait before trying again, from the `Retry-After` header.
+    pub retry_after: Option<Duration>,
+}
+
+impl ApiError {
+    /// Read the error from the body of a response, which is usually a json object with an `error`
+    /// field, falling back to the body itself.
+    fn new(status: StatusCode, body: &str) -> Self {
+        let error = serde_json::from_str::<serde_json::Value>(body).ok();
+        let status = error
+            .as_ref()
+            .and_then(|error| error.get("status")?.as_u64())
+            .and_then(|status| StatusCode::from_u16(status as u16).ok())
+            .unwrap_or(status);
+        let message = error
+            .as_ref()
+            .and_then(|error| error.get("error")?.as_str())
+            .unwrap_or_else(|| body.trim())
+            .to_string();
+
+        Self {
+            status,
+            message,
+            retry_after: None,
+        }
+    }
+}
 
-static TEXT_SYNTH: OnceCell<TextSynth> = OnceCell::new();
-static ENGINE: Lazy<Engine> = Lazy::new(|| get().engine(config::get().engine_definition.clone()));
+/// Read the `Retry-After` header, which is either a number of seconds or a date.
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let value = headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
 
-pub fn initialize(api_key: String) -> anyhow::Result<&'static TextSynth> {
-    TEXT_SYNTH
-        .get_or_try_init(|| TextSynth::try_new(api_key))
-        .context("failed to initialize the textsynth client")
+    match value.parse::<u64>() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => {
+            let date = httpdate::parse_http_date(value).ok()?;
+
+            // a date in the past means the request can be made again right away
+            Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+        }
+    }
 }
 
-pub fn get() -> &'static TextSynth {
-    TEXT_SYNTH.get().expect("textsynth not initialized")
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the textsynth api answered with {}", self.status)?;
+
+        if !self.message.is_empty() {
+            write!(f, ": {}", self.message)?;
+        }
+
+        Ok(())
+    }
 }
 
-pub fn engine() -> &'static Engine<'static> {
-    &ENGINE
+impl std::error::Error for ApiError {}
+
+/// The parameters of a text completion request. They are expected to be checked already.
+#[derive(Serialize)]
+pub struct CompletionRequest {
+    pub prompt: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u16>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct TextCompletion {
+    text: String,
+
+    #[serde(default)]
+    truncated_prompt: bool,
+
+    #[serde(default)]
+    total_tokens: Option<usize>,
+
+    #[serde(default)]
+    input_tokens: Option<usize>,
+
+    #[serde(default)]
+    output_tokens: Option<usize>,
+}
+
+impl TextCompletion {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn truncated_prompt(&self) -> bool {
+        self.truncated_prompt
+    }
+
+    /// The number of tokens the request used, which newer versions of the api count as input and
+    /// output tokens instead.
+    pub fn total_tokens(&self) -> Option<usize> {
+        self.total_tokens
+            .or_else(|| Some(self.input_tokens? + self.output_tokens?))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LogProbabilities {
+    logprob: f64,
+    is_greedy: bool,
+
+    #[serde(default, alias = "input_tokens")]
+    total_tokens: usize,
+}
+
+impl LogProbabilities {
+    pub fn log_probability(&self) -> f64 {
+        self.logprob
+    }
+
+    pub fn is_greedy(&self) -> bool {
+        self.is_greedy
+    }
+
+    pub fn total_tokens(&self) -> usize {
+        self.total_tokens
+    }
+}
+
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: String,
+    timeout: Option<Duration>,
+}
+
+impl Client {
+    pub fn new(api_key: String, connection: &Connection) -> anyhow::Result<Self> {
+        let base_url = connection
+            .base_url
+            .as_deref()
+            .unwrap_or(DEFAULT_BASE_URL)
+            .trim_end_matches('/')
+            .to_string();
+        let timeout = connection.timeout()?;
+        debug!(
+            "connection: base url {}, proxy {}, timeout {:?}, connect timeout {:?}, extra certificate authorities {}",
+            base_url,
+            connection
+                .proxy
+                .as_deref()
+                .map(config::redact_url)
+                .unwrap_or_else(|| "(none)".to_string()),
+            timeout,
+            connection.connect_timeout()?,
+            connection
+                .ca_bundle
+                .as_ref()
+                .map(|ca_bundle| ca_bundle.display().to_string())
+                .unwrap_or_else(|| "(none)".to_string())
+        );
+
+        Ok(Self {
+            http: http_client(connection)?,
+            base_url,
+            api_key,
+            timeout,
+        })
+    }
+
+    pub fn engine(&self, definition: EngineDefinition) -> Engine<'_> {
+        Engine {
+            client: self,
+            definition,
+        }
+    }
+
+    /// Send a json request, turning an unsuccessful answer into an [`ApiError`].
+    async fn post(
+        &self,
+        url: &str,
+        body: &serde_json::Value,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<reqwest::Response> {
+        let mut request = self.http.post(url).bearer_auth(&self.api_key).json(body);
+
+        if let Some(timeout) = timeout {
+            request = request.timeout(timeout);
+        }
+
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("failed to connect to {}", url.bold()))?;
+        let status = response.status();
+
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let retry_after = retry_after(response.headers());
+        let body = response.text().await.unwrap_or_default();
+
+        Err(ApiError {
+            retry_after,
+            ..ApiError::new(status, &body)
+        }
+        .into())
+    }
+}
+
+pub struct Engine<'a> {
+    client: &'a Client,
+    pub definition: EngineDefinition,
+}
+
+impl Engine<'_> {
+    fn url(&self, endpoint: &str) -> String {
+        format!(
+            "{}/v1/engines/{}/{}",
+            self.client.base_url,
+            engine_id(&self.definition),
+            endpoint
+        )
+    }
+
+    pub async fn text_completion(
+        &self,
+        request: &CompletionRequest,
+    ) -> anyhow::Result<TextCompletion> {
+        let url = self.url("completions");
+        let body = serde_json::to_value(request).context("failed to serialize the request")?;
+
+        self.client
+            .post(&url, &body, self.client.timeout)
+            .await?
+            .json()
+            .await
+            .context("failed to parse output from textsynth api to json")
+    }
+
+    /// Start streaming a text completion. Waiting for the api to start answering, and then for
+    /// each chunk, is limited to the timeout.
+    pub async fn stream(&self, request: &CompletionRequest) -> anyhow::Result<CompletionStream> {
+        let url = self.url("completions");
+        let mut body = serde_json::to_value(request).context("failed to serialize the request")?;
+        body["stream"] = true.into();
+        let response = limit(self.client.post(&url, &body, None), self.client.timeout).await??;
+
+        Ok(CompletionStream {
+            response,
+            buffer: Vec::new(),
+            timeout: self.client.timeout,
+        })
+    }
+
+    /// Split the text into the tokens of the engine.
+    pub async fn tokenize(&self, text: &str) -> anyhow::Result<Vec<u32>> {
+        let url = self.url("tokenize");
+        let body = serde_json::json!({ "text": text });
+
+        self.client
+            .post(&url, &body, self.client.timeout)
+            .await?
+            .json::<Tokens>()
+            .await
+            .context("failed to parse output from textsynth api to json")
+            .map(|tokens| tokens.tokens)
+    }
+
+    pub async fn log_probabilities(
+        &self,
+        context: &str,
+        continuation: &str,
+    ) -> anyhow::Result<LogProbabilities> {
+        if continuation.is_empty() {
+            anyhow::bail!("the continuation must not be empty")
+        }
+
+        let url = self.url("logprob");
+        let body = serde_json::json!({ "context": context, "continuation": continuation });
+
+        self.client
+            .post(&url, &body, self.client.timeout)
+            .await?
+            .json()
+            .await
+            .context("failed to parse output from textsynth api to json")
+    }
+}
+
+#[derive(Deserialize)]
+struct Tokens {
+    tokens: Vec<u32>,
+}
+
+/// A streamed text completion. The api answers with json objects which are each followed by an
+/// empty line.
+pub struct CompletionStream {
+    response: reqwest::Response,
+    buffer: Vec<u8>,
+    timeout: Option<Duration>,
+}
+
+impl CompletionStream {
+    /// The next chunk of the completion, or nothing once the stream is over.
+    pub async fn next(&mut self) -> anyhow::Result<Option<TextCompletion>> {
+        loop {
+            if let Some(object) = next_object(&mut self.buffer) {
+                match parse_chunk(&object, self.response.status())? {
+                    Some(text_completion) => return Ok(Some(text_completion)),
+                    None => continue,
+                }
+            }
+
+            match limit(self.response.chunk(), self.timeout)
+                .await?
+                .context("failed to read the stream")?
+            {
+                Some(bytes) => self.buffer.extend_from_slice(&bytes),
+
+                // the last object may not be followed by an empty line
+                None => {
+                    let object = std::mem::take(&mut self.buffer);
+                    return parse_chunk(&object, self.response.status());
+                }
+            }
+        }
+    }
+}
+
+/// Take the next complete object off the buffer, along with the empty line which follows it.
+fn next_object(buffer: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let end = buffer.windows(2).position(|window| window == b"\n\n")?;
+
+    Some(buffer.drain(..end + 2).collect())
+}
+
+/// Parse an object of a stream, which may be an error instead of a chunk of the completion.
+fn parse_chunk(object: &[u8], status: StatusCode) -> anyhow::Result<Option<TextCompletion>> {
+    let object = String::from_utf8_lossy(object);
+
+    if object.trim().is_empty() {
+        return Ok(None);
+    }
+
+    let value = serde_json::from_str::<serde_json::Value>(&object)
+        .context("failed to parse output from textsynth api to json")?;
+
+    if value.get("error").is_some() {
+        return Err(ApiError::new(status, &object).into());
+    }
+
+    serde_json::from_value(value)
+        .context("failed to get next text completion")
+        .map(Some)
+}
+
+async fn limit<T>(
+    future: impl Future<Output = T>,
+    duration: Option<Duration>,
+) -> anyhow::Result<T> {
+    match duration {
+        Some(duration) => tokio::time::timeout(duration, future)
+            .await
+            .with_context(|| format!("the timeout of {} seconds elapsed", duration.as_secs_f64())),
+        None => Ok(future.await),
+    }
+}
+
+pub fn initialize(api_key: String, connection: &Connection) -> anyhow::Result<&'static Client> {
+    CLIENT.get_or_try_init(|| {
+        Client::new(api_key, connection).context("failed to initialize the textsynth client")
+    })
+}
+
+pub fn get() -> &'static Client {
+    CLIENT.get().expect("textsynth not initialized")
+}
+
+/// The current engine. Requests which are already running keep the engine they started with
+/// when it is switched.
+pub fn engine() -> Arc<Engine<'static>> {
+    ENGINE.read().expect("engine lock poisoned").clone()
+}
+
+/// Use the specified engine definition for the following requests, with the same client.
+pub fn set_engine(engine_definition: EngineDefinition) {
+    *ENGINE.write().expect("engine lock poisoned") = Arc::new(get().engine(engine_definition));
+}
+
+/// The id the api knows the engine definition by.
+pub fn engine_id(engine_definition: &EngineDefinition) -> &str {
+    match engine_definition {
+        EngineDefinition::GptJ6B => "gptj_6B",
+        EngineDefinition::Boris6B => "boris_6B",
+        EngineDefinition::FairseqGpt13B => "fairseq_gpt_13B",
+        EngineDefinition::Custom(custom) => custom.id(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{engine_id, next_object, parse_chunk, retry_after, ApiError};
+    use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+    use reqwest::StatusCode;
+    use std::time::{Duration, SystemTime};
+    use textsynth::prelude::{CustomEngineDefinition, EngineDefinition};
+
+    /// Feed the chunks to the stream framing the way [`super::CompletionStream`] does, collecting
+    /// the texts of the completion.
+    fn texts(chunks: &[&str]) -> anyhow::Result<Vec<String>> {
+        let mut buffer = Vec::new();
+        let mut texts = Vec::new();
+
+        for chunk in chunks {
+            buffer.extend_from_slice(chunk.as_bytes());
+
+            while let Some(object) = next_object(&mut buffer) {
+                if let Some(text_completion) = parse_chunk(&object, StatusCode::OK)? {
+                    texts.push(text_completion.text().to_string());
+                }
+            }
+        }
+
+        if let Some(text_completion) = parse_chunk(&buffer, StatusCode::OK)? {
+            texts.push(text_completion.text().to_string());
+        }
+
+        Ok(texts)
+    }
+
+    fn headers(retry_after: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(retry_after).unwrap());
+        headers
+    }
+
+    #[test]
+    fn objects_in_a_single_chunk() {
+        let texts = texts(&["{\"text\": \"a\"}\n\n{\"text\": \"b\"}\n\n"]).unwrap();
+        assert_eq!(texts, ["a", "b"]);
+    }
+
+    #[test]
+    fn objects_split_across_chunks() {
+        let texts = texts(&["{\"te", "xt\": \"a\"}\n", "\n{\"text\": ", "\"b\"}\n\n"]).unwrap();
+        assert_eq!(texts, ["a", "b"]);
+    }
+
+    #[test]
+    fn trailing_object_without_an_empty_line() {
+        let texts = texts(&["{\"text\": \"a\"}\n\n{\"text\": \"b\"}"]).unwrap();
+        assert_eq!(texts, ["a", "b"]);
+    }
+
+    #[test]
+    fn partial_object_is_held_until_complete() {
+        let mut buffer = b"{\"text\": \"a\"}\n\n{\"text\"".to_vec();
+
+        assert!(next_object(&mut buffer).is_some());
+        assert!(next_object(&mut buffer).is_none());
+        assert_eq!(buffer, b"{\"text\"");
+    }
+
+    #[test]
+    fn truncated_trailing_object_is_an_error() {
+        assert!(texts(&["{\"text\": \"a\"}\n\n{\"text\""]).is_err());
+    }
+
+    #[test]
+    fn error_object_mid_stream() {
+        let error = texts(&[
+            "{\"text\": \"a\"}\n\n",
+            "{\"error\": \"out of credits\", \"status\": 402}\n\n",
+            "{\"text\": \"b\"}\n\n",
+        ])
+        .unwrap_err();
+        let error = error.downcast_ref::<ApiError>().unwrap();
+
+        assert_eq!(error.status, StatusCode::PAYMENT_REQUIRED);
+        assert_eq!(error.message, "out of credits");
+    }
+
+    #[test]
+    fn retry_after_in_seconds() {
+        assert_eq!(
+            retry_after(&headers(" 120 ")),
+            Some(Duration::from_secs(120))
+        );
+    }
+
+    #[test]
+    fn retry_after_as_a_date() {
+        let date = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(120));
+        let duration = retry_after(&headers(&date)).unwrap();
+
+        // the date only has a precision of seconds
+        assert!(duration > Duration::from_secs(110) && duration <= Duration::from_secs(120));
+    }
+
+    #[test]
+    fn retry_after_a_date_in_the_past() {
+        let date = httpdate::fmt_http_date(SystemTime::now() - Duration::from_secs(120));
+        assert_eq!(retry_after(&headers(&date)), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn retry_after_which_is_missing_or_invalid() {
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+        assert_eq!(retry_after(&headers("soon")), None);
+    }
+
+    #[test]
+    fn engine_id_of_a_custom_engine_definition() {
+        let engine_definition =
+            EngineDefinition::Custom(CustomEngineDefinition::new("my_engine".to_string(), 1024));
+        assert_eq!(engine_id(&engine_definition), "my_engine");
+        assert_eq!(engine_id(&EngineDefinition::GptJ6B), "gptj_6B");
+    }
 }
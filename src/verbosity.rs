@@ -0,0 +1,140 @@
+//! Diagnostics, which are written to stderr so that stdout only holds the results, and how many
+//! of them are shown.
+
+use std::env;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+pub const LOG_VAR: &str = "SYNTHTEXT_LOG";
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Normal as u8);
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    /// Only warnings and errors.
+    Quiet,
+
+    /// Information and tips as well.
+    Normal,
+
+    /// What is being done, and how long it took.
+    Verbose,
+
+    /// Every attempt of every request and its parameters.
+    Debug,
+}
+
+impl Level {
+    /// The level from the --quiet and --verbose flags, if either was passed.
+    pub fn from_flags(quiet: bool, verbose: u8) -> Option<Self> {
+        match (quiet, verbose) {
+            (true, _) => Some(Self::Quiet),
+            (false, 0) => None,
+            (false, 1) => Some(Self::Verbose),
+            (false, _) => Some(Self::Debug),
+        }
+    }
+}
+
+/// Pick the level from the flags, falling back to the [`LOG_VAR`] environment variable, which can
+/// be `quiet`, `normal`, `verbose` or `debug`.
+pub fn initialize(flags: Option<Level>) {
+    let level = flags.or_else(|| {
+        let value = env::var(LOG_VAR).ok()?;
+
+        match value.to_lowercase().as_str() {
+            "quiet" => Some(Level::Quiet),
+            "normal" => Some(Level::Normal),
+            "verbose" => Some(Level::Verbose),
+            "debug" => Some(Level::Debug),
+            _ => {
+                warn!("ignoring unknown {} value {}", LOG_VAR, value);
+                None
+            }
+        }
+    });
+
+    if let Some(level) = level {
+        set(level)
+    }
+}
+
+pub fn set(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed)
+}
+
+pub fn enabled(level: Level) -> bool {
+    LEVEL.load(Ordering::Relaxed) >= level as u8
+}
+
+/// Print a message with a colored prefix to stderr, if the level is enabled.
+macro_rules! log {
+    ($level:ident, $prefix:expr, $($arg:tt)*) => {
+        if $crate::verbosity::enabled($crate::verbosity::Level::$level) {
+            eprintln!("{} {}", $prefix, format_args!($($arg)*));
+        }
+    };
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::verbosity::log!(
+            Quiet,
+            owo_colors::OwoColorize::bold(&owo_colors::OwoColorize::red(&"error:")),
+            $($arg)*
+        )
+    };
+}
+
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        $crate::verbosity::log!(
+            Quiet,
+            owo_colors::OwoColorize::bold(&owo_colors::OwoColorize::yellow(&"warning:")),
+            $($arg)*
+        )
+    };
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::verbosity::log!(
+            Normal,
+            owo_colors::OwoColorize::bold(&owo_colors::OwoColorize::blue(&"info:")),
+            $($arg)*
+        )
+    };
+}
+
+macro_rules! tip {
+    ($($arg:tt)*) => {
+        $crate::verbosity::log!(
+            Normal,
+            owo_colors::OwoColorize::bold(&owo_colors::OwoColorize::green(&"tip:")),
+            $($arg)*
+        )
+    };
+}
+
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        $crate::verbosity::log!(
+            Verbose,
+            owo_colors::OwoColorize::bold(&owo_colors::OwoColorize::cyan(&"verbose:")),
+            $($arg)*
+        )
+    };
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        $crate::verbosity::log!(
+            Debug,
+            owo_colors::OwoColorize::dimmed(&"debug:"),
+            $($arg)*
+        )
+    };
+}
+
+// `warn` is also the name of a built-in attribute, so it can only be re-exported under another name
+pub(crate) use {debug, error, info, log, tip, verbose, warning as warn};